@@ -0,0 +1,149 @@
+//
+// 画面の取り込みと画像ファイルへの書き出し
+//
+//   表示内容を RGB24 の画像にして、PNG で保存する。
+//     実機の解像度 : 表示内容(64x32、128x64 など)をそのまま、パレットの色で
+//     表示の大きさ : ちらつき対策、ドット絵向けの拡大、ブラウン管風の効果をかけて、ウインドウ上の大きさで
+//   ファイル名は、ROM 名に日時(UTC)を付けて重ならないようにする。
+//
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crt::CrtEffects;
+use crate::machine::Machine;
+use crate::upscale::Upscaler;
+
+/// 表示の大きさで保存するときの拡大
+pub struct Scale {
+    pub size: (usize, usize), // 保存する大きさ (横, 縦)
+    pub upscaler: Upscaler,   // ドット絵向けの拡大
+    pub crt: CrtEffects,      // ブラウン管風の効果
+}
+
+impl Scale {
+    /// 画像(RGB24)を保存する大きさにする
+    ///
+    pub fn apply(&self, rgb: &[u8], size: (usize, usize)) -> Vec<u8> {
+        let (upscaled, upscaled_size) = self.upscaler.apply(rgb, size);
+        display_image(&upscaled, upscaled_size, self.size, &self.crt)
+    }
+}
+
+/// 表示内容を RGB24 にする
+///
+///   戻り値: 画像の大きさ (横, 縦)
+pub fn frame_rgb(machine: &dyn Machine, dst: &mut Vec<u8>) -> (usize, usize) {
+    let (width, height, frame) = machine.frame();
+
+    dst.clear();
+    for yy in 0..height {
+        for xx in 0..width {
+            let color = machine.pixel_color(xx, yy, frame[yy * width + xx]);
+            dst.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+    (width, height)
+}
+
+/// 画像(RGB24)を表示の大きさにする
+///
+///   ブラウン管風の効果が有効ならその効果をかけ、無効なら最近傍補間で拡大する
+pub fn display_image(
+    src: &[u8],
+    src_size: (usize, usize),
+    dst_size: (usize, usize),
+    crt: &CrtEffects,
+) -> Vec<u8> {
+    let mut dst = Vec::new();
+    if crt.enabled {
+        crt.process(src, src_size, &mut dst, dst_size);
+        return dst;
+    }
+
+    let ((src_w, src_h), (dst_w, dst_h)) = (src_size, dst_size);
+    if src_w == 0 || src_h == 0 {
+        return vec![0; dst_w * dst_h * 3];
+    }
+    dst.reserve(dst_w * dst_h * 3);
+    for y in 0..dst_h {
+        let sy = y * src_h / dst_h;
+        for x in 0..dst_w {
+            let sx = x * src_w / dst_w;
+            let i = (sy * src_w + sx) * 3;
+            dst.extend_from_slice(&src[i..i + 3]);
+        }
+    }
+    dst
+}
+
+/// 画像(RGB24)を PNG で保存する
+///
+pub fn save_png<P: AsRef<Path>>(path: P, rgb: &[u8], size: (usize, usize)) -> Result<(), String> {
+    let path = path.as_ref();
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| error(&e))?;
+    }
+    let file = File::create(path).map_err(|e| error(&e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size.0 as u32, size.1 as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    writer.write_image_data(rgb).map_err(|e| error(&e))?;
+    writer.finish().map_err(|e| error(&e))
+}
+
+/// 保存するファイルのパス
+///
+///   dir/<ROM 名>-<年月日>-<時分秒>.<拡張子>(既にあれば -2、-3 ... を付ける)
+pub fn timestamped_path<P: AsRef<Path>>(dir: P, rom_name: &str, ext: &str) -> PathBuf {
+    let stem = Path::new(rom_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("chip8".to_string());
+    let base = format!("{}-{}", stem, timestamp());
+
+    let dir = dir.as_ref();
+    let mut path = dir.join(format!("{}.{}", base, ext));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", base, n, ext));
+        n += 1;
+    }
+    path
+}
+
+/// 現在の日時(UTC) "YYYYMMDD-HHMMSS"
+///
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs()) as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // 1970-01-01 からの日数を年月日にする
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
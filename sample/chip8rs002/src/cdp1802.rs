@@ -0,0 +1,629 @@
+//
+// RCA CDP1802 CPU コア
+//
+//   COSMAC VIP に搭載されていた 8bit CPU。
+//   16本の16bitレジスタ R0〜RF のうち、P で指定したレジスタがプログラムカウンタ、
+//   X で指定したレジスタがデータポインタになる。
+//   命令は1バイト(上位4bit: I、下位4bit: N)で、
+//   ロングブランチ命令(Cx)が3マシンサイクル、それ以外は2マシンサイクルで実行される。
+//
+
+/// CPU から見たメモリ、I/O ポート、EFフラグ
+pub trait Bus {
+    /// メモリ読み出し
+    fn read(&mut self, addr: u16) -> u8;
+    /// メモリ書き込み
+    fn write(&mut self, addr: u16, val: u8);
+    /// OUT 1〜7 命令
+    fn output(&mut self, port: u8, val: u8);
+    /// INP 1〜7 命令
+    fn input(&mut self, port: u8) -> u8;
+    /// EF1〜4 入力の状態
+    fn ef(&self, n: u8) -> bool;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Cdp1802 {
+    pub r: [u16; 16], // R0 - RF Register
+    pub p: u8,        // プログラムカウンタとして使うレジスタ番号
+    pub x: u8,        // データポインタとして使うレジスタ番号
+    pub d: u8,        // D (アキュムレータ)
+    pub df: u8,       // DF (キャリー/ボロー) 0 or 1
+    pub t: u8,        // T (割り込み時の X,P 退避先)
+    pub ie: bool,     // 割り込み許可
+    pub q: bool,      // Q 出力
+    pub idle: bool,   // IDL 命令で停止中
+}
+
+impl Cdp1802 {
+    /// リセット状態の CPU を作成
+    ///
+    pub fn new() -> Cdp1802 {
+        let mut cpu = Cdp1802::default();
+        cpu.reset();
+        cpu
+    }
+
+    /// リセット
+    ///   X, P, Q, R0 を 0 に、IE を 1 にする(それ以外は不定)
+    pub fn reset(&mut self) {
+        self.x = 0;
+        self.p = 0;
+        self.q = false;
+        self.ie = true;
+        self.r[0] = 0;
+        self.idle = false;
+    }
+
+    /// 割り込み要求
+    ///   IE=1 のときだけ受け付け、受け付けた場合は true を返す(1マシンサイクル)
+    pub fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+        self.t = (self.x << 4) | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    /// DMA出力
+    ///   M(R0) を読み出して R0 を+1する(1マシンサイクル)
+    pub fn dma_out<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let val = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        val
+    }
+
+    /// 1命令を実行し、消費したマシンサイクル数を返す
+    ///
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        // IDL 中は割り込みか DMA が来るまで何もしない
+        if self.idle {
+            return 1;
+        }
+
+        // 命令フェッチ
+        let op = self.fetch(bus);
+        let n = (op & 0x0F) as usize;
+        let x = self.x as usize;
+
+        // デコード、実行
+        match op >> 4 {
+            0x0 => {
+                if n == 0 {
+                    // 00 - IDL
+                    self.idle = true;
+                } else {
+                    // 0N - LDN
+                    self.d = bus.read(self.r[n]);
+                }
+            }
+            0x1 => {
+                // 1N - INC
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x2 => {
+                // 2N - DEC
+                self.r[n] = self.r[n].wrapping_sub(1);
+            }
+            0x3 => {
+                // 3N - ショートブランチ
+                let cond = match n {
+                    0x0 => true,                      // BR
+                    0x1 => self.q,                    // BQ
+                    0x2 => self.d == 0,               // BZ
+                    0x3 => self.df != 0,              // BDF
+                    0x4..=0x7 => bus.ef(n as u8 - 3), // B1 - B4
+                    0x8 => false,                     // SKP
+                    0x9 => !self.q,                   // BNQ
+                    0xA => self.d != 0,               // BNZ
+                    0xB => self.df == 0,              // BNF
+                    _ => !bus.ef(n as u8 - 0xB),      // BN1 - BN4
+                };
+                let pc = self.r[self.p as usize];
+                if cond {
+                    let lo = bus.read(pc);
+                    self.r[self.p as usize] = (pc & 0xFF00) | lo as u16;
+                } else {
+                    self.r[self.p as usize] = pc.wrapping_add(1);
+                }
+            }
+            0x4 => {
+                // 4N - LDA
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x5 => {
+                // 5N - STR
+                bus.write(self.r[n], self.d);
+            }
+            0x6 => match n {
+                0x0 => {
+                    // 60 - IRX
+                    self.r[x] = self.r[x].wrapping_add(1);
+                }
+                0x1..=0x7 => {
+                    // 61-67 - OUT
+                    let val = bus.read(self.r[x]);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                    bus.output(n as u8, val);
+                }
+                0x8 => {
+                    // 68 - 1802 では未定義(何もしない)
+                }
+                _ => {
+                    // 69-6F - INP
+                    let val = bus.input(n as u8 - 8);
+                    bus.write(self.r[x], val);
+                    self.d = val;
+                }
+            },
+            0x7 => match n {
+                0x0 | 0x1 => {
+                    // 70 - RET / 71 - DIS
+                    let val = bus.read(self.r[x]);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                    self.x = val >> 4;
+                    self.p = val & 0x0F;
+                    self.ie = n == 0;
+                }
+                0x2 => {
+                    // 72 - LDXA
+                    self.d = bus.read(self.r[x]);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                }
+                0x3 => {
+                    // 73 - STXD
+                    bus.write(self.r[x], self.d);
+                    self.r[x] = self.r[x].wrapping_sub(1);
+                }
+                0x4 => {
+                    // 74 - ADC
+                    let m = bus.read(self.r[x]);
+                    self.add(m, self.df);
+                }
+                0x5 => {
+                    // 75 - SDB
+                    let m = bus.read(self.r[x]);
+                    self.sub(m, self.d, self.df);
+                }
+                0x6 => {
+                    // 76 - SHRC
+                    let carry = self.df;
+                    self.df = self.d & 0x01;
+                    self.d = (self.d >> 1) | (carry << 7);
+                }
+                0x7 => {
+                    // 77 - SMB
+                    let m = bus.read(self.r[x]);
+                    self.sub(self.d, m, self.df);
+                }
+                0x8 => {
+                    // 78 - SAV
+                    bus.write(self.r[x], self.t);
+                }
+                0x9 => {
+                    // 79 - MARK
+                    self.t = (self.x << 4) | self.p;
+                    bus.write(self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                }
+                0xA => {
+                    // 7A - REQ
+                    self.q = false;
+                }
+                0xB => {
+                    // 7B - SEQ
+                    self.q = true;
+                }
+                0xC => {
+                    // 7C - ADCI
+                    let m = self.fetch(bus);
+                    self.add(m, self.df);
+                }
+                0xD => {
+                    // 7D - SDBI
+                    let m = self.fetch(bus);
+                    self.sub(m, self.d, self.df);
+                }
+                0xE => {
+                    // 7E - SHLC
+                    let carry = self.df;
+                    self.df = self.d >> 7;
+                    self.d = (self.d << 1) | carry;
+                }
+                _ => {
+                    // 7F - SMBI
+                    let m = self.fetch(bus);
+                    self.sub(self.d, m, self.df);
+                }
+            },
+            0x8 => {
+                // 8N - GLO
+                self.d = self.r[n] as u8;
+            }
+            0x9 => {
+                // 9N - GHI
+                self.d = (self.r[n] >> 8) as u8;
+            }
+            0xA => {
+                // AN - PLO
+                self.r[n] = (self.r[n] & 0xFF00) | self.d as u16;
+            }
+            0xB => {
+                // BN - PHI
+                self.r[n] = (self.r[n] & 0x00FF) | (self.d as u16) << 8;
+            }
+            0xC => {
+                // CN - ロングブランチ、ロングスキップ(3マシンサイクル)
+                self.long_branch(bus, n as u8);
+                return 3;
+            }
+            0xD => {
+                // DN - SEP
+                self.p = n as u8;
+            }
+            0xE => {
+                // EN - SEX
+                self.x = n as u8;
+            }
+            _ => {
+                // FN - 論理演算、算術演算
+                let m = match n {
+                    0x6 | 0xE => 0,
+                    0x0..=0x7 => bus.read(self.r[x]),
+                    _ => self.fetch(bus),
+                };
+                match n & 0x7 {
+                    0x0 => self.d = m,             // LDX / LDI
+                    0x1 => self.d |= m,            // OR / ORI
+                    0x2 => self.d &= m,            // AND / ANI
+                    0x3 => self.d ^= m,            // XOR / XRI
+                    0x4 => self.add(m, 0),         // ADD / ADI
+                    0x5 => self.sub(m, self.d, 1), // SD / SDI
+                    0x6 => {
+                        if n == 0x6 {
+                            // F6 - SHR
+                            self.df = self.d & 0x01;
+                            self.d >>= 1;
+                        } else {
+                            // FE - SHL
+                            self.df = self.d >> 7;
+                            self.d <<= 1;
+                        }
+                    }
+                    _ => self.sub(self.d, m, 1), // SM / SMI
+                }
+            }
+        }
+
+        2
+    }
+
+    /// M(R(P)) を読み出して R(P) を+1する
+    ///
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let pc = self.r[self.p as usize];
+        self.r[self.p as usize] = pc.wrapping_add(1);
+        bus.read(pc)
+    }
+
+    /// D = D + val + carry (DF にキャリーをセット)
+    ///
+    fn add(&mut self, val: u8, carry: u8) {
+        let sum = self.d as u16 + val as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = (sum >> 8) as u8 & 0x01;
+    }
+
+    /// D = a - b - (1 - df) (ボロー無しなら DF=1)
+    ///
+    fn sub(&mut self, a: u8, b: u8, df: u8) {
+        let diff = a as i16 - b as i16 - (1 - df as i16);
+        self.d = diff as u8;
+        self.df = if diff >= 0 { 1 } else { 0 };
+    }
+
+    /// CN 命令(ロングブランチ、ロングスキップ)
+    ///
+    fn long_branch<B: Bus>(&mut self, bus: &mut B, n: u8) {
+        let pc = self.r[self.p as usize];
+        let (cond, skip) = match n {
+            0x0 => (true, false),         // LBR
+            0x1 => (self.q, false),       // LBQ
+            0x2 => (self.d == 0, false),  // LBZ
+            0x3 => (self.df != 0, false), // LBDF
+            0x4 => return,                // NOP
+            0x5 => (!self.q, true),       // LSNQ
+            0x6 => (self.d != 0, true),   // LSNZ
+            0x7 => (self.df == 0, true),  // LSNF
+            0x8 => (true, true),          // LSKP
+            0x9 => (!self.q, false),      // LBNQ
+            0xA => (self.d != 0, false),  // LBNZ
+            0xB => (self.df == 0, false), // LBNF
+            0xC => (self.ie, true),       // LSIE
+            0xD => (self.q, true),        // LSQ
+            0xE => (self.d == 0, true),   // LSZ
+            _ => (self.df != 0, true),    // LSDF
+        };
+
+        if cond && !skip {
+            let hi = bus.read(pc);
+            let lo = bus.read(pc.wrapping_add(1));
+            self.r[self.p as usize] = (hi as u16) << 8 | lo as u16;
+        } else if cond || !skip {
+            // スキップ成立、またはブランチ不成立の場合は2バイト読み飛ばす
+            self.r[self.p as usize] = pc.wrapping_add(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// テスト用のバス(64KB の RAM と EF 線、I/O の記録)
+    struct MockBus {
+        mem: Vec<u8>,
+        ef: [bool; 4],
+        outputs: Vec<(u8, u8)>,
+    }
+
+    impl MockBus {
+        fn new(program: &[u8]) -> MockBus {
+            let mut mem = vec![0_u8; 0x10000];
+            mem[..program.len()].copy_from_slice(program);
+            MockBus {
+                mem,
+                ef: [false; 4],
+                outputs: Vec::new(),
+            }
+        }
+    }
+
+    impl Bus for MockBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
+
+        fn output(&mut self, port: u8, val: u8) {
+            self.outputs.push((port, val));
+        }
+
+        fn input(&mut self, _port: u8) -> u8 {
+            0
+        }
+
+        fn ef(&self, n: u8) -> bool {
+            self.ef[n as usize - 1]
+        }
+    }
+
+    /// D を設定してから1命令実行する
+    fn run_with_d(program: &[u8], d: u8) -> Cdp1802 {
+        let mut bus = MockBus::new(program);
+        let mut cpu = Cdp1802::new();
+        cpu.d = d;
+        cpu.step(&mut bus);
+        cpu
+    }
+
+    #[test]
+    fn short_branch_timing() {
+        // 30 - BR: 2サイクル、下位バイトだけ置き換える
+        let mut bus = MockBus::new(&[0x30, 0x42]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(cpu.step(&mut bus), 2);
+        assert_eq!(cpu.r[0], 0x0042);
+
+        // 32 - BZ 不成立: 2サイクル、分岐先を読み飛ばす
+        let mut bus = MockBus::new(&[0x32, 0x42]);
+        let mut cpu = Cdp1802::new();
+        cpu.d = 1;
+        assert_eq!(cpu.step(&mut bus), 2);
+        assert_eq!(cpu.r[0], 0x0002);
+
+        // 38 - SKP: 次の1バイトを読み飛ばす
+        let mut bus = MockBus::new(&[0x38, 0x42]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(cpu.step(&mut bus), 2);
+        assert_eq!(cpu.r[0], 0x0002);
+
+        // ページ境界の分岐は同じページの中に戻る
+        let mut bus = MockBus::new(&[]);
+        bus.mem[0x01FF] = 0x30;
+        bus.mem[0x0200] = 0x10;
+        let mut cpu = Cdp1802::new();
+        cpu.r[0] = 0x01FF;
+        cpu.step(&mut bus);
+        assert_eq!(cpu.r[0], 0x0210);
+    }
+
+    #[test]
+    fn long_branch_timing() {
+        // C0 - LBR: 3サイクル、16ビットのアドレスに分岐
+        let mut bus = MockBus::new(&[0xC0, 0x12, 0x34]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.r[0], 0x1234);
+
+        // C2 - LBZ 不成立: 3サイクル、2バイト読み飛ばす
+        let mut bus = MockBus::new(&[0xC2, 0x12, 0x34]);
+        let mut cpu = Cdp1802::new();
+        cpu.d = 1;
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.r[0], 0x0003);
+
+        // C4 - NOP: 3サイクル
+        let mut bus = MockBus::new(&[0xC4, 0x12]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.r[0], 0x0001);
+
+        // CE - LSZ 成立: 2バイト読み飛ばす
+        let mut bus = MockBus::new(&[0xCE, 0x12, 0x34]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.r[0], 0x0003);
+
+        // CE - LSZ 不成立: 次の命令に進む
+        let mut bus = MockBus::new(&[0xCE, 0x12, 0x34]);
+        let mut cpu = Cdp1802::new();
+        cpu.d = 1;
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.r[0], 0x0001);
+    }
+
+    #[test]
+    fn interrupt_entry() {
+        let mut cpu = Cdp1802::new();
+        cpu.x = 0x5;
+        cpu.p = 0x3;
+
+        // T に X,P を保存し、P=1、X=2、IE=0 にする
+        assert!(cpu.interrupt());
+        assert_eq!(cpu.t, 0x53);
+        assert_eq!((cpu.p, cpu.x), (1, 2));
+        assert!(!cpu.ie);
+
+        // IE=0 の間は受け付けない
+        cpu.x = 0x7;
+        assert!(!cpu.interrupt());
+        assert_eq!(cpu.t, 0x53);
+        assert_eq!(cpu.x, 0x7);
+
+        // 割り込みで IDL から抜ける
+        let mut bus = MockBus::new(&[0x00]);
+        let mut cpu = Cdp1802::new();
+        cpu.step(&mut bus);
+        assert_eq!(cpu.step(&mut bus), 1);
+        assert!(cpu.interrupt());
+        assert!(!cpu.idle);
+
+        // 70 - RET: M(R(X)) から X,P を戻し IE=1 にする
+        let mut bus = MockBus::new(&[]);
+        bus.mem[0x0100] = 0x70;
+        bus.mem[0x0200] = 0x53;
+        let mut cpu = Cdp1802::new();
+        cpu.interrupt();
+        cpu.r[1] = 0x0100;
+        cpu.r[2] = 0x0200;
+        cpu.step(&mut bus);
+        assert_eq!((cpu.x, cpu.p), (5, 3));
+        assert_eq!(cpu.r[2], 0x0201);
+        assert!(cpu.ie);
+    }
+
+    #[test]
+    fn dma_out() {
+        let mut bus = MockBus::new(&[]);
+        bus.mem[0x0300] = 0xAA;
+        bus.mem[0x0301] = 0x55;
+        let mut cpu = Cdp1802::new();
+        cpu.r[0] = 0x0300;
+
+        // M(R0) を読み出して R0 を+1する
+        assert_eq!(cpu.dma_out(&mut bus), 0xAA);
+        assert_eq!(cpu.dma_out(&mut bus), 0x55);
+        assert_eq!(cpu.r[0], 0x0302);
+
+        // R0 は 16ビットで1周する
+        cpu.r[0] = 0xFFFF;
+        cpu.dma_out(&mut bus);
+        assert_eq!(cpu.r[0], 0x0000);
+    }
+
+    #[test]
+    fn shift_carry() {
+        // F6 - SHR: DF に bit0
+        let cpu = run_with_d(&[0xF6], 0x81);
+        assert_eq!((cpu.d, cpu.df), (0x40, 1));
+        let cpu = run_with_d(&[0xF6], 0x80);
+        assert_eq!((cpu.d, cpu.df), (0x40, 0));
+
+        // FE - SHL: DF に bit7
+        let cpu = run_with_d(&[0xFE], 0x81);
+        assert_eq!((cpu.d, cpu.df), (0x02, 1));
+        let cpu = run_with_d(&[0xFE], 0x01);
+        assert_eq!((cpu.d, cpu.df), (0x02, 0));
+    }
+
+    #[test]
+    fn add_sub_carry() {
+        // FC - ADI: キャリーで DF=1
+        let cpu = run_with_d(&[0xFC, 0x01], 0xFF);
+        assert_eq!((cpu.d, cpu.df), (0x00, 1));
+        let cpu = run_with_d(&[0xFC, 0x01], 0xFE);
+        assert_eq!((cpu.d, cpu.df), (0xFF, 0));
+
+        // FD - SDI: D = M - D、ボロー無しなら DF=1
+        let cpu = run_with_d(&[0xFD, 0x05], 0x03);
+        assert_eq!((cpu.d, cpu.df), (0x02, 1));
+        let cpu = run_with_d(&[0xFD, 0x03], 0x05);
+        assert_eq!((cpu.d, cpu.df), (0xFE, 0));
+
+        // FF - SMI: D = D - M
+        let cpu = run_with_d(&[0xFF, 0x03], 0x05);
+        assert_eq!((cpu.d, cpu.df), (0x02, 1));
+        let cpu = run_with_d(&[0xFF, 0x05], 0x03);
+        assert_eq!((cpu.d, cpu.df), (0xFE, 0));
+
+        // 等しい値の減算はボロー無し
+        let cpu = run_with_d(&[0xFF, 0x05], 0x05);
+        assert_eq!((cpu.d, cpu.df), (0x00, 1));
+
+        // F4 - ADD: M(R(X)) を加算
+        let mut bus = MockBus::new(&[0xF4]);
+        bus.mem[0x0100] = 0x80;
+        let mut cpu = Cdp1802::new();
+        cpu.x = 1;
+        cpu.r[1] = 0x0100;
+        cpu.d = 0x80;
+        cpu.step(&mut bus);
+        assert_eq!((cpu.d, cpu.df), (0x00, 1));
+    }
+
+    #[test]
+    fn ef_branches() {
+        // 34 - B1 / 3C - BN1 は EF1 に従う
+        for ef1 in [false, true] {
+            let mut bus = MockBus::new(&[0x34, 0x42]);
+            bus.ef[0] = ef1;
+            let mut cpu = Cdp1802::new();
+            cpu.step(&mut bus);
+            assert_eq!(cpu.r[0], if ef1 { 0x0042 } else { 0x0002 });
+
+            let mut bus = MockBus::new(&[0x3C, 0x42]);
+            bus.ef[0] = ef1;
+            let mut cpu = Cdp1802::new();
+            cpu.step(&mut bus);
+            assert_eq!(cpu.r[0], if ef1 { 0x0002 } else { 0x0042 });
+        }
+
+        // 36 - B3 は EF3 だけを見る
+        let mut bus = MockBus::new(&[0x36, 0x42]);
+        bus.ef = [true, true, false, true];
+        let mut cpu = Cdp1802::new();
+        cpu.step(&mut bus);
+        assert_eq!(cpu.r[0], 0x0002);
+    }
+
+    #[test]
+    fn output_port() {
+        // 62 - OUT 2: M(R(X)) を出力して R(X) を+1する
+        let mut bus = MockBus::new(&[0x62, 0x0A]);
+        let mut cpu = Cdp1802::new();
+        cpu.step(&mut bus);
+        assert_eq!(bus.outputs, vec![(2, 0x0A)]);
+        assert_eq!(cpu.r[0], 0x0002);
+    }
+}
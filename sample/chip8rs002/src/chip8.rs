@@ -0,0 +1,873 @@
+//
+// CHIP-8 インタプリタ
+//
+
+use rand::prelude::*;
+use sdl2::pixels::Color;
+
+use crate::machine::{Machine, Reset, SoundEvent};
+use crate::megachip::MegaChip;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+
+#[allow(non_snake_case)]
+pub struct Chip8 {
+    pub mem: Vec<u8>, // メインメモリ(通常 4KB、MegaChip8 は 16MB)
+
+    pub reg_V: [u8; 16],     // V0 - V15   Register
+    pub reg_I: usize,        // Index Register
+    pub reg_delay_timer: u8, // Delay Timer Register
+    pub reg_sound_timer: u8, // Sound Timer Register
+
+    pub pc: usize,                      // Program Counter
+    pub stack: [u16; Chip8::STACKSIZE], // 16byte Stack Area
+    pub stack_p: usize,                 // Stack Pointer
+
+    pub vram: Vec<u8>, // width x height の画素(0 or 1)
+    pub width: usize,  // 横ピクセル数(プラットフォームで決まる)
+    pub height: usize, // 縦ライン数(プラットフォームで決まる)
+    pub rand255: ThreadRng,
+
+    pub wait_for_key: bool,
+
+    pub quirks: Quirks,        // 互換動作の設定
+    pub vblank: bool,          // フレーム境界(60Hz)を通過したか
+    pub wait_for_vblank: bool, // Dxyn がフレーム境界待ちで停止中
+
+    pub platform: Platform, // プラットフォーム
+    pub palette: Palette,   // 表示色
+
+    // CHIP-8X (VP-590 カラーボード)
+    pub bg_color: usize, // 背景色(X_BG_COLORS の番号)
+    pub fg_colors: [[u8; Self::XSIZE / 8]; Self::YSIZE], // 前景色(横8ピクセル x 1ライン単位)
+    pub io_port: u8,     // 出力ポート(FxF8)
+
+    pub rom: Vec<u8>, // 読み込んだ ROM イメージ(ハードリセットで読み込み直す)
+
+    pub mega: Option<MegaChip>, // MegaChip8 拡張(MegaChip8 プラットフォームのみ)
+    pub sound_event: Option<SoundEvent>, // 未処理のサンプル再生要求
+}
+
+impl Chip8 {
+    pub const CELLSIZE: usize = 12; // 12 x 12ピクセルサイズ
+                                    //（ピクセルサイズは適当に変更してください）
+    pub const XSIZE: usize = 64; // 横64 ピクセル
+    pub const YSIZE: usize = 32; // 縦32 ライン
+    pub const F_COLOR: sdl2::pixels::Color = Color::RGB(0, 200, 0); // ピクセルの色(Green)
+    pub const B_COLOR: sdl2::pixels::Color = Color::RGB(0, 0, 0); // 背景色(Black)
+
+    pub const STACKSIZE: usize = 16; // スタックサイズ 16個
+
+    // 16進数字(0〜F)のフォント 4x5ピクセル
+    pub const FONT: [u8; 16 * 5] = [
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+        0x20, 0x60, 0x20, 0x20, 0x70, // 1
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+    ];
+
+    // CHIP-8X の色(VP-590)
+    pub const X_COLORS: [Color; 8] = [
+        Color::RGB(0, 0, 0),       // 0: Black
+        Color::RGB(255, 0, 0),     // 1: Red
+        Color::RGB(0, 0, 255),     // 2: Blue
+        Color::RGB(255, 0, 255),   // 3: Violet
+        Color::RGB(0, 255, 0),     // 4: Green
+        Color::RGB(255, 255, 0),   // 5: Yellow
+        Color::RGB(0, 255, 255),   // 6: Aqua
+        Color::RGB(255, 255, 255), // 7: White
+    ];
+    // 02A0 で Blue -> Black -> Green -> Red の順に切り替わる背景色
+    pub const X_BG_COLORS: [Color; 4] = [
+        Color::RGB(0, 0, 128), // Blue
+        Color::RGB(0, 0, 0),   // Black
+        Color::RGB(0, 128, 0), // Green
+        Color::RGB(128, 0, 0), // Red
+    ];
+    pub const X_DEFAULT_FG: u8 = 1; // 前景色の初期値(Red)
+
+    /// Chip8構造体を初期化
+    ///
+    pub fn new(platform: Platform, quirks: Quirks) -> Chip8 {
+        let (width, height) = platform.display_size();
+
+        let mut chip8 = Chip8 {
+            mem: vec![0_u8; platform.mem_size()],
+
+            reg_V: [0_u8; 16],
+            reg_I: 0,
+            reg_delay_timer: 0,
+            reg_sound_timer: 0,
+
+            pc: platform.entry_point(), // 実行開始アドレス
+            stack: [0_u16; 16],
+            stack_p: 0,
+
+            // VRAM領域。オール0 で初期化
+            vram: vec![0_u8; width * height],
+            width,
+            height,
+            rand255: rand::thread_rng(),
+            wait_for_key: false,
+
+            quirks,
+            vblank: false,
+            wait_for_vblank: false,
+
+            platform,
+            palette: Palette::default(),
+
+            bg_color: 0,
+            fg_colors: [[Chip8::X_DEFAULT_FG; Chip8::XSIZE / 8]; Chip8::YSIZE],
+            io_port: 0,
+
+            rom: Vec::new(),
+
+            mega: if platform == Platform::MegaChip8 {
+                Some(MegaChip::new())
+            } else {
+                None
+            },
+            sound_event: None,
+        };
+
+        // フォントをメインメモリに配置
+        let font = platform.font_address();
+        chip8.mem[font..(font + Chip8::FONT.len())].copy_from_slice(&Chip8::FONT);
+
+        chip8
+    }
+
+    /// ROMイメージを読み込みアドレスから配置する
+    ///   (ファイルは呼び出し側で読み込む。読めなかった場合のエラーは呼び出し側で扱う)
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        let addr = self.platform.load_address();
+        let size = rom.len().min(self.mem.len() - addr);
+
+        //for p in 0..size {
+        //    self.mem[0x200 + p] = tmpmem[p];
+        //}
+        self.mem[addr..(size + addr)].copy_from_slice(&rom[..size]);
+        self.rom = rom;
+    }
+
+    /// レジスタ表示
+    ///
+    pub fn report_reg(&self) {
+        // 汎用レジスタ
+        println!(
+            "[PC:{:04x}] {:02x} {:02x}",
+            self.pc,
+            self.mem[self.pc],
+            self.mem[self.pc + 1]
+        );
+        for i in 0..16 {
+            print!(" V{:X}:{:02x}", i, self.reg_V[i]);
+            if i == 7 {
+                println!();
+            }
+        }
+
+        // 特殊レジスタ
+        println!();
+        println!(
+            " I:{:04x}  DT:{:02x}  SP:{:02x}",
+            self.reg_I, self.reg_delay_timer, self.stack_p
+        );
+
+        // スタック領域
+        println!(" stack:{:?}", self.stack);
+
+        // メインメモリ
+        //print!("  mem[0b37]:{:?}", self.mem[0x0b37]);
+        println!();
+    }
+
+    /// エラーの内容(エラーの起きた PC と命令を付ける)
+    ///
+    pub fn error_mes<T: AsRef<str>>(&self, mes: T) -> String {
+        let byte = |offset: usize| self.mem[(self.pc + offset) % self.mem.len()];
+        format!(
+            "{} [PC:{:04x}] {:02x}{:02x} {:02x}{:02x}",
+            mes.as_ref(),
+            self.pc,
+            byte(0),
+            byte(1),
+            byte(2),
+            byte(3)
+        )
+    }
+
+    /// 1命令をフェッチ、デコード、実行する
+    ///
+    ///   keys : 押下中のキー値(押されていないキーは 0xFF)
+    ///   keys2: CHIP-8X の2つ目のキーパッド
+    pub fn exec(&mut self, keys: &[u8; 16], keys2: &[u8; 16]) -> Result<(), String> {
+        let chip8x = self.platform == Platform::Chip8X;
+
+        let mut update_pc = false;
+
+        // 命令フェッチ
+        //
+        // 2byte,16bit値のうち
+        // -- 12 〜 15bit目の値
+        let d0 = (self.mem[self.pc] & 0xF0) >> 4;
+        // --  8 〜 11bit目
+        #[allow(unused_parens)]
+        let d1 = (self.mem[self.pc] & 0x0F);
+        // --  4 〜 7bit目
+        let d2 = (self.mem[self.pc + 1] & 0xF0) >> 4;
+        // --  0 〜 3bit目(最下位)
+        #[allow(unused_parens)]
+        let d3 = (self.mem[self.pc + 1] & 0x0F);
+
+        // デコード、実行
+        //
+        match (d0, d1, d2, d3) {
+            (0x0, 0x0, 0xE, 0x0) if self.mega.as_ref().is_some_and(|m| m.enabled) => {
+                // 00E0 - (MegaChip) 裏画面を表示して消去
+                if let Some(mega) = self.mega.as_mut() {
+                    mega.present();
+                }
+            }
+            (0x0, 0x0, 0xE, 0x0) => {
+                // 00E0 - CLS
+                self.vram.fill(0);
+            }
+            (0x0, 0x0, 0x1, m) if self.mega.is_some() && m <= 1 => {
+                // 0010 - (MegaChip) MegaChip モード OFF
+                // 0011 - (MegaChip) MegaChip モード ON
+                if let Some(mega) = self.mega.as_mut() {
+                    mega.enabled = m == 1;
+                    mega.clear();
+                }
+                self.vram.fill(0);
+            }
+            (0x0, 0x1, n1, n0) if self.mega.is_some() => {
+                // 01nn nnnn - (MegaChip) I = nnnnnn (24bit アドレス)
+                let nn = ((n1 as usize) << 4) | n0 as usize;
+                let nnnn = (self.mem[self.pc + 2] as usize) << 8 | self.mem[self.pc + 3] as usize;
+                self.reg_I = (nn << 16) | nnnn;
+                self.pc += 4;
+                update_pc = true;
+            }
+            (0x0, 0x2, n1, n0) if self.mega.is_some() => {
+                // 02nn - (MegaChip) I から nn 色分のパレットを読み込む
+                let count = ((n1 as usize) << 4) | n0 as usize;
+                if let Some(mega) = self.mega.as_mut() {
+                    mega.load_palette(&self.mem, self.reg_I, count);
+                }
+            }
+            (0x0, d, n1, n0) if self.mega.is_some() && (0x3..=0x5).contains(&d) => {
+                // 03nn - (MegaChip) スプライトの横幅 (0 は 256)
+                // 04nn - (MegaChip) スプライトの高さ (0 は 256)
+                // 05nn - (MegaChip) 画面全体の不透明度
+                let nn = (n1 << 4) | n0;
+                let size = if nn == 0 { 256 } else { nn as usize };
+                if let Some(mega) = self.mega.as_mut() {
+                    match d {
+                        0x3 => mega.sprite_w = size,
+                        0x4 => mega.sprite_h = size,
+                        _ => mega.alpha = nn,
+                    }
+                }
+            }
+            (0x0, 0x6, 0x0, n) if self.mega.is_some() => {
+                // 060n - (MegaChip) I のデジタルサンプルを再生 (n=0 ループ再生)
+                //   先頭6バイト: サンプル周波数(2byte)、サンプル数(3byte)、予約(1byte)
+                //   メモリの終わりを越える分は先頭に折り返す(パレットの読み込みと同じ)
+                let addr = self.reg_I;
+                let size = self.mem.len();
+                let byte = |offset: usize| self.mem[(addr + offset) % size];
+                let rate = (byte(0) as u32) << 8 | byte(1) as u32;
+                let len = ((byte(2) as usize) << 16 | (byte(3) as usize) << 8 | byte(4) as usize)
+                    .min(size);
+                self.sound_event = Some(SoundEvent::Play {
+                    data: (0..len).map(|i| byte(6 + i)).collect(),
+                    rate,
+                    looping: n == 0,
+                });
+            }
+            (0x0, 0x7, 0x0, 0x0) if self.mega.is_some() => {
+                // 0700 - (MegaChip) サンプル再生を停止
+                self.sound_event = Some(SoundEvent::Stop);
+            }
+            (0x0, 0x8, 0x0, n) if self.mega.is_some() => {
+                // 080n - (MegaChip) スプライトの合成方法
+                //   0: 通常 1: 25% 2: 50% 3: 75% 4: 加算 5: 乗算
+                if let Some(mega) = self.mega.as_mut() {
+                    mega.blend = n;
+                }
+            }
+            (0x0, 0x9, n1, n0) if self.mega.is_some() => {
+                // 09nn - (MegaChip) 衝突判定に使うパレット番号
+                if let Some(mega) = self.mega.as_mut() {
+                    mega.collision = (n1 << 4) | n0;
+                }
+            }
+            (0x0, 0x2, 0x3, 0x0) if self.platform == Platform::HiRes => {
+                // 0230 - (64x64 hi-res CHIP-8) CLS
+                self.vram.fill(0);
+            }
+            (0x0, 0x0, 0xE, 0xE) => {
+                // 00EE - RET
+                if self.stack_p > 0 {
+                    self.pc = self.stack[self.stack_p - 1] as usize;
+                    update_pc = true;
+                    self.stack_p -= 1;
+                } else {
+                    // スタックに空である
+                    return Err("Stack Empty".to_string());
+                }
+            }
+            (0x0, 0x2, 0xA, 0x0) if chip8x => {
+                // 02A0 - (CHIP-8X) 背景色を切り替え
+                self.bg_color = (self.bg_color + 1) % Chip8::X_BG_COLORS.len();
+            }
+            (0x0, n2, n1, n0) => {
+                // 0nnn - SYS addr
+                self.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
+                update_pc = true;
+            }
+            (0x1, n2, n1, n0) => {
+                // 1nnn - JP addr
+                self.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
+                update_pc = true;
+            }
+            (0x2, n2, n1, n0) => {
+                // 2nnn - CALL addr
+                if self.stack_p < (16 - 1) {
+                    self.stack[self.stack_p] = ((self.pc + 2) & 0xFFFF) as u16;
+                    self.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
+                    update_pc = true;
+
+                    self.stack_p += 1;
+                } else {
+                    // スタックが一杯で、空きが無い
+                    return Err("Stack Full".to_string());
+                }
+            }
+            (0x3, x, k1, k0) => {
+                // 3xkk - SE Vx, byte
+                if self.reg_V[x as usize] == (k1 << 4) | k0 {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0x4, x, k1, k0) => {
+                // 4xkk - SNE Vx, byte
+                if self.reg_V[x as usize] != (k1 << 4 | k0) {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0x5, x, y, 0x1) if chip8x => {
+                // 5xy1 - (CHIP-8X) Vx, Vy の各ニブルの下位3bitを個別に加算
+                let sum = (self.reg_V[x as usize] & 0x77) + (self.reg_V[y as usize] & 0x77);
+                self.reg_V[x as usize] = sum & 0x77;
+            }
+            (0x5, x, y, 0x0) => {
+                // 5xy0 - SE Vx, Vy
+                if self.reg_V[x as usize] == self.reg_V[y as usize] {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0x6, x, k1, k0) => {
+                // 6xkk - LD Vx, byte
+                self.reg_V[x as usize] = (k1 << 4) | k0;
+            }
+            (0x7, x, k1, k0) => {
+                // 7xkk - ADD Vx, byte
+                let sum = self.reg_V[x as usize] as u16 + ((k1 as u16) << 4 | (k0 as u16) & 0xFF);
+
+                // Carry Check
+                self.reg_V[0xF] = if sum & 0x100 != 0 { 1 } else { 0 };
+
+                self.reg_V[x as usize] = sum as u8;
+            }
+            (0x8, x, y, 0x0) => {
+                // 8xy0 - LD Vx, Vy
+                self.reg_V[x as usize] = self.reg_V[y as usize];
+            }
+            (0x8, x, y, 0x1) => {
+                // 8xy1 - OR Vx, Vy
+                self.reg_V[x as usize] |= self.reg_V[y as usize];
+            }
+            (0x8, x, y, 0x2) => {
+                // 8xy2 - AND Vx, Vy
+                self.reg_V[x as usize] &= self.reg_V[y as usize];
+            }
+            (0x8, x, y, 0x3) => {
+                // 8xy3 - XOR Vx, Vy
+                self.reg_V[x as usize] ^= self.reg_V[y as usize];
+            }
+            (0x8, x, y, 0x4) => {
+                // 8xy4 - ADD Vx, Vy
+                let sum = self.reg_V[x as usize] as u16 + self.reg_V[y as usize] as u16;
+
+                // Carry Check
+                self.reg_V[0xF] = if sum & 0x100 != 0 { 1 } else { 0 };
+
+                self.reg_V[x as usize] = sum as u8;
+            }
+            (0x8, x, y, 0x5) => {
+                // 8xy5 - SUB Vx, Vy
+                let vx = self.reg_V[x as usize];
+                let vy = self.reg_V[y as usize];
+
+                self.reg_V[0xF] = if vx > vy { 1 } else { 0 };
+                self.reg_V[x as usize] = vx.wrapping_sub(vy);
+            }
+            (0x8, x, y, 0x6) => {
+                // 8xy6 - SHR Vx {, Vy}
+                let vx = if self.quirks.shift_vy {
+                    self.reg_V[y as usize]
+                } else {
+                    self.reg_V[x as usize]
+                };
+
+                // LSB check
+                self.reg_V[0xF] = if vx & 0x01 != 0x00 { 1 } else { 0 };
+
+                self.reg_V[x as usize] = vx >> 1;
+            }
+            (0x8, x, y, 0x7) => {
+                // 8xy7 - SUBN Vx, Vy
+                let vx = self.reg_V[x as usize];
+                let vy = self.reg_V[y as usize];
+
+                if vy > vx {
+                    self.reg_V[0xF] = 1;
+                    self.reg_V[x as usize] = vy.wrapping_sub(vx);
+                } else {
+                    self.reg_V[0xF] = 0;
+                };
+            }
+            (0x8, x, y, 0xE) => {
+                // 8xyE - SHL Vx {, Vy}
+                let vx = if self.quirks.shift_vy {
+                    self.reg_V[y as usize]
+                } else {
+                    self.reg_V[x as usize]
+                };
+
+                self.reg_V[0xF] = if (vx & 0x80) != 0 { 1 } else { 0 };
+                self.reg_V[x as usize] = vx << 1;
+            }
+            (0x9, x, y, 0x0) => {
+                // 9xy0 - SNE Vx, Vy
+                if self.reg_V[x as usize] != self.reg_V[y as usize] {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0xA, n2, n1, n0) => {
+                // Annn - LD I, addr
+                self.reg_I = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
+            }
+            (0xB, x, y, n) if chip8x => {
+                // Bxyn - (CHIP-8X) 前景色の設定
+                //   Vx   : 横方向の範囲(上位4bit: 開始位置、下位4bit: 幅-1、8ピクセル単位)
+                //   Vx+1 : 縦方向の範囲
+                //          n = 0 のとき 上位4bit: 開始位置、下位4bit: 高さ-1 (4ライン単位)
+                //          n > 0 のとき 開始ライン(高さは n ライン)
+                //   Vy   : 色番号(0〜7)
+                let hx = self.reg_V[x as usize];
+                let vx = self.reg_V[(x as usize + 1) & 0xF];
+                let color = self.reg_V[y as usize] & 0x7;
+
+                let cols = (hx >> 4) as usize..=((hx >> 4) + (hx & 0xF)) as usize;
+                let lines = if n == 0 {
+                    (vx >> 4) as usize * 4..((vx >> 4) + (vx & 0xF) + 1) as usize * 4
+                } else {
+                    vx as usize..vx as usize + n as usize
+                };
+                for line in lines {
+                    for col in cols.clone() {
+                        self.fg_colors[line % Chip8::YSIZE][col % (Chip8::XSIZE / 8)] = color;
+                    }
+                }
+            }
+            (0xB, n2, n1, n0) => {
+                // Bnnn - JP V0, addr
+                self.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize
+                    + self.reg_V[0] as usize;
+                update_pc = true;
+            }
+            (0xC, x, k1, k0) => {
+                // Cxkk - RND Vx, byte
+                let kk = (k1 << 4) | k0;
+                self.reg_V[x as usize] = self.rand255.gen::<u8>() & kk;
+            }
+            (0xD, x, y, n) => {
+                // Dxyn - DRW Vx, Vy, nibble
+                // アドレスIのｎバイトのスプライトを読み出し(VX,VY)位置に描画する
+                let xx = self.reg_V[x as usize] as usize;
+                let yy = self.reg_V[y as usize] as usize;
+                let addr = self.reg_I;
+
+                // display_wait 有効時は、次のフレーム境界まで描画を待つ
+                // (PCを進めず、フレーム境界後に同じ命令を再実行する)
+                if self.quirks.display_wait && !self.vblank {
+                    self.wait_for_vblank = true;
+                } else if let Some(mega) = self.mega.as_mut().filter(|m| m.enabled) {
+                    self.wait_for_vblank = false;
+                    self.vblank = false;
+
+                    // (MegaChip) パレット番号のスプライトを裏画面へ描画
+                    let collision = mega.draw_sprite(&self.mem, addr, xx, yy);
+                    self.reg_V[0xF] = collision as u8;
+                } else {
+                    self.wait_for_vblank = false;
+                    self.vblank = false;
+
+                    // Update VRAM
+                    self.reg_V[0xf] = 0;
+                    for byte in 0..(n as usize) {
+                        let val = self.mem[addr + byte];
+                        for bit in 0..8_usize {
+                            let sprite_pixel = (val >> (7 - bit)) & 0x1;
+                            let pos =
+                                ((yy + byte) % self.height) * self.width + (xx + bit) % self.width;
+                            let mut vram_pixel = self.vram[pos];
+
+                            self.reg_V[0xF] |= sprite_pixel & vram_pixel;
+                            vram_pixel ^= sprite_pixel;
+
+                            self.vram[pos] = vram_pixel;
+                        }
+                    }
+                }
+            }
+            (0xE, x, 0x9, 0xE) => {
+                // Ex9E - SKP Vx
+                // "キーが押されているか"チェック
+                'keyloop: for key in keys.iter() {
+                    if self.reg_V[x as usize] == *key {
+                        self.pc += 4;
+                        update_pc = true;
+                        break 'keyloop;
+                    }
+                }
+            }
+            (0xE, x, 0xA, 0x1) => {
+                // ExA1 - SKNP Vx
+                // "キーが押されていないか"チェック
+                let mut not_det_flg = true;
+                'keyloop: for key in keys.iter() {
+                    if self.reg_V[x as usize] == *key {
+                        not_det_flg = false;
+                        break 'keyloop;
+                    }
+                }
+
+                if not_det_flg {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0xE, x, 0xF, 0x2) if chip8x => {
+                // ExF2 - (CHIP-8X) 2つ目のキーパッドの Vx キーが押されていればスキップ
+                if keys2.contains(&self.reg_V[x as usize]) {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0xE, x, 0xF, 0x5) if chip8x => {
+                // ExF5 - (CHIP-8X) 2つ目のキーパッドの Vx キーが押されていなければスキップ
+                if !keys2.contains(&self.reg_V[x as usize]) {
+                    self.pc += 4;
+                    update_pc = true;
+                }
+            }
+            (0xF, x, 0x0, 0x7) => {
+                // Fx07 - LD Vx, D
+                self.reg_V[x as usize] = self.reg_delay_timer;
+            }
+            (0xF, x, 0x0, 0xA) => {
+                // Fx0A - LD Vx, K
+                // キーが入力されるまで全ての実行をストップする。キーが押されるとその値をVxにセットする。
+                self.wait_for_key = true;
+
+                'keyloop: for key in keys.iter() {
+                    if *key != 0xFF {
+                        self.reg_V[x as usize] = *key;
+                        self.wait_for_key = false;
+                        break 'keyloop;
+                    }
+                }
+            }
+            (0xF, x, 0xF, 0x8) if chip8x => {
+                // FxF8 - (CHIP-8X) Vx を出力ポートへ出力(音程の設定)
+                self.io_port = self.reg_V[x as usize];
+            }
+            (0xF, x, 0xF, 0xB) if chip8x => {
+                // FxFB - (CHIP-8X) 入力ポートから Vx へ読み込む
+                // (入力デバイスは接続されていないので常に 0)
+                self.reg_V[x as usize] = 0;
+            }
+            (0xF, x, 0x1, 0x5) => {
+                // Fx15 - LD DT, Vx
+                self.reg_delay_timer = self.reg_V[x as usize];
+            }
+            (0xF, x, 0x1, 0x8) => {
+                // Fx18 - LD ST, Vx
+                self.reg_sound_timer = self.reg_V[x as usize];
+            }
+            (0xF, x, 0x1, 0xE) => {
+                // Fx1E - ADD I, Vx
+                self.reg_I += (self.reg_V[x as usize]) as usize;
+            }
+            (0xF, x, 0x2, 0x09) => {
+                // Fx29 - LD F, Vx
+                self.reg_I = self.platform.font_address() + self.reg_V[x as usize] as usize * 5;
+            }
+            (0xF, x, 0x3, 0x3) => {
+                // Fx33 - LD B, Vx
+                let addr = self.reg_I;
+                let val = self.reg_V[x as usize];
+                // 10進表記の百の位、十の位、一の位の値を取る
+                self.mem[addr] = val / 100;
+                self.mem[addr + 1] = (val % 100) / 10;
+                self.mem[addr + 2] = val % 10;
+            }
+            (0xF, x, 0x5, 0x5) => {
+                // Fx55 - LD [I], Vx
+                for i in 0..(x + 1) as usize {
+                    if i < 16 {
+                        self.mem[self.reg_I + i] = self.reg_V[i];
+                    } else {
+                        break;
+                    }
+                }
+                match self.quirks.memory_increment {
+                    MemoryIncrement::Both => self.reg_I += x as usize + 1,
+                    MemoryIncrement::ByX => self.reg_I += x as usize,
+                    MemoryIncrement::Unchanged | MemoryIncrement::LoadOnly => {}
+                }
+            }
+            (0xF, x, 0x6, 0x5) => {
+                // Fx65 - LD Vx, [I]
+                for i in 0..(x + 1) as usize {
+                    if i < 16 {
+                        self.reg_V[i] = self.mem[self.reg_I + i];
+                    } else {
+                        break;
+                    }
+                }
+                match self.quirks.memory_increment {
+                    MemoryIncrement::Both | MemoryIncrement::LoadOnly => {
+                        self.reg_I += x as usize + 1
+                    }
+                    MemoryIncrement::ByX => self.reg_I += x as usize,
+                    MemoryIncrement::Unchanged => {}
+                }
+            }
+            _ => {
+                // 命令コードが無かったら、直ちに終了
+                return Err("Not Support Instruction".to_string());
+            }
+        }
+
+        // プログラムカウンタを+2進める(2byte、16bit分)
+        // 下記の場合はプログラムカウンタPCを更新しない
+        //   1)wait_for_key: true キー入力待ちで、実行を一時停止中のため
+        //   2)update_pc   : true 既に各分岐命令でPCを更新済みなので、ここでは更新しない
+        //   3)wait_for_vblank: true Dxyn がフレーム境界待ちで、実行を一時停止中のため
+        if !self.wait_for_key && !self.wait_for_vblank && !update_pc {
+            self.pc += 2;
+        }
+
+        Ok(())
+    }
+
+    /// 各タイマーレジスタをカウントダウン(フレーム境界毎に呼び出す)
+    ///
+    pub fn tick_timers(&mut self) {
+        // ディレイタイマーレジスタの更新
+        if self.reg_delay_timer > 0 {
+            self.reg_delay_timer -= 1;
+        }
+        // サウンドタイマーレジスタの更新
+        if self.reg_sound_timer > 0 {
+            self.reg_sound_timer -= 1;
+        }
+
+        // フレーム境界を通過(Dxyn の描画待ちを解除)
+        //   待っていなければフラグを下ろし、次の Dxyn は次のフレーム境界まで待つ
+        //   (同じフレーム内の2つ目の Dxyn も待つ。COSMAC VIP と同じ)
+        self.vblank = self.wait_for_vblank;
+    }
+}
+
+impl Machine for Chip8 {
+    fn step(&mut self, keys: &[u8; 16], keys2: &[u8; 16]) -> Result<(), String> {
+        self.exec(keys, keys2)
+    }
+
+    fn tick_frame(&mut self) {
+        self.tick_timers();
+    }
+
+    fn frame(&self) -> (usize, usize, &[u8]) {
+        match &self.mega {
+            Some(mega) if mega.enabled => (MegaChip::XSIZE, MegaChip::YSIZE, &mega.front_idx),
+            _ => (self.width, self.height, &self.vram),
+        }
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        match &self.mega {
+            Some(mega) if mega.enabled => (MegaChip::XSIZE, MegaChip::YSIZE),
+            _ => self.platform.display_size(),
+        }
+    }
+
+    fn pixel_color(&self, x: usize, y: usize, pixel: u8) -> Color {
+        if let Some(mega) = self.mega.as_ref().filter(|m| m.enabled) {
+            return mega.pixel_color(x, y);
+        }
+
+        match (self.platform, pixel == 1) {
+            // CHIP-8X: ゾーン毎の前景色と、画面全体の背景色
+            (Platform::Chip8X, true) => Self::X_COLORS[self.fg_colors[y][x / 8] as usize],
+            (Platform::Chip8X, false) => Self::X_BG_COLORS[self.bg_color],
+            _ => self.palette.color(pixel),
+        }
+    }
+
+    fn background_color(&self) -> Color {
+        // MegaChip の表画面は透明(黒)で消える
+        if self.mega.as_ref().is_some_and(|m| m.enabled) {
+            return Color::RGB(0, 0, 0);
+        }
+        self.pixel_color(0, 0, 0)
+    }
+
+    fn reset(&mut self, kind: Reset) {
+        // 電源投入時と同じ状態から、メモリ、ROM イメージ、表示色を戻す
+        let mut chip8 = Chip8::new(self.platform, self.quirks);
+        chip8.palette = self.palette.clone();
+        match kind {
+            Reset::Soft => {
+                chip8.mem = std::mem::take(&mut self.mem);
+                chip8.rom = std::mem::take(&mut self.rom);
+            }
+            Reset::Hard => chip8.load_rom(std::mem::take(&mut self.rom)),
+        }
+        *self = chip8;
+    }
+
+    fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+    }
+
+    fn take_sound_event(&mut self) -> Option<SoundEvent> {
+        self.sound_event.take()
+    }
+
+    fn wait_status(&self) -> Option<&'static str> {
+        if self.wait_for_vblank {
+            Some("Wait for vblank")
+        } else if self.wait_for_key {
+            Some("Wait for any key")
+        } else {
+            None
+        }
+    }
+
+    fn report(&self) {
+        self.report_reg();
+    }
+
+    fn error_mes(&self, mes: &str) -> String {
+        Chip8::error_mes(self, mes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM を読み込み、steps 命令実行する
+    fn run(platform: Platform, rom: &[u8], steps: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(platform, Quirks::default());
+        chip8.load_rom(rom.to_vec());
+        let keys = [0xFF_u8; 16];
+        for _ in 0..steps {
+            chip8.exec(&keys, &keys).unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn megachip_lores_sprite_wraps() {
+        // 0200: 603E  V0 = 62
+        // 0202: 6100  V1 = 0
+        // 0204: A20A  I = 020A
+        // 0206: D011  (V0, V1) に1ライン描画
+        // 0208: 1208  ループ
+        // 020A: F0    スプライト(4ピクセル)
+        let rom = [
+            0x60, 0x3E, 0x61, 0x00, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xF0,
+        ];
+        let chip8 = run(Platform::MegaChip8, &rom, 4);
+
+        // 0011 までは 64x32 で、右端からはみ出した分は左端に回り込む
+        let (width, height, frame) = chip8.frame();
+        assert_eq!((width, height), (64, 32));
+        assert_eq!(chip8.display_size(), (64, 32));
+        let lit: Vec<usize> = (0..width).filter(|&x| frame[x] == 1).collect();
+        assert_eq!(lit, vec![0, 1, 62, 63]);
+    }
+
+    #[test]
+    fn memory_increment_by_x() {
+        // 0200: A300  I = 0300
+        // 0202: F255  V0〜V2 を保存
+        // 0204: F165  V0〜V1 を読み込み
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0xF1, 0x65];
+        let mut chip8 = Chip8::new(
+            Platform::Chip8,
+            Quirks {
+                memory_increment: MemoryIncrement::ByX,
+                ..Quirks::default()
+            },
+        );
+        chip8.load_rom(rom.to_vec());
+        let keys = [0xFF_u8; 16];
+        for _ in 0..3 {
+            chip8.exec(&keys, &keys).unwrap();
+        }
+
+        // x+1 ではなく x だけ進む
+        assert_eq!(chip8.reg_I, 0x300 + 2 + 1);
+    }
+
+    #[test]
+    fn megachip_mode_size() {
+        // 0200: 0011  MegaChip モード ON
+        let chip8 = run(Platform::MegaChip8, &[0x00, 0x11], 1);
+        let (width, height, _) = chip8.frame();
+        assert_eq!((width, height), (MegaChip::XSIZE, MegaChip::YSIZE));
+        assert_eq!(chip8.display_size(), (MegaChip::XSIZE, MegaChip::YSIZE));
+    }
+}
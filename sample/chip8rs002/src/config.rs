@@ -0,0 +1,377 @@
+//
+// 設定ファイル
+//
+//   ~/.config/chip8rs/config.toml ($XDG_CONFIG_HOME があればその下)を起動時に読み込む。
+//   [rom."<ファイル名 または SHA-1>"] の節で ROM 毎に設定を上書きできる。
+//   全ての項目はコマンドラインでも指定でき、優先順位は
+//     コマンドライン > ROM 毎の節 > 共通の設定 > ROM データベース/推定 > 既定値
+//
+//   例)
+//     platform = "chip8"
+//     fg_color = "#00c800"
+//     keypad = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V"]
+//
+//     [rom."pong.ch8"]
+//     quirks = "vip"
+//     tickrate = 15
+//
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::keymap;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+/// 設定項目(指定の無い項目は None)
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Settings {
+    pub platform: Option<String>,                      // プラットフォーム名
+    pub quirks: Option<String>,                        // 互換動作のプロファイル名
+    pub tickrate: Option<u32>,                         // 1フレーム(60Hz)あたりの実行命令数
+    pub keypad: Option<Vec<String>>, // 4x4 のキーパッドに割り当てるキー(SDL のキー名 16個)
+    pub keymap_preset: Option<String>, // キーボード配列のプリセット(keypad の代わりに使う)
+    pub palette: Option<String>,     // パレット名(組み込みのテーマか [palettes] の名前)
+    pub fg_color: Option<String>,    // ピクセルの色 "#rrggbb"
+    pub bg_color: Option<String>,    // 背景色 "#rrggbb"
+    pub cell_size: Option<usize>,    // 1ピクセルの大きさ(64x32 のときのウインドウ上のピクセル数)
+    pub wait_ms: Option<u32>,        // 実行ループ1回毎の待ち時間 [ms]
+    pub rom_dir: Option<String>,     // メニューの ROM の選択で最初に表示するディレクトリ
+    pub cycle_ms: Option<u32>,       // タイマー(DT/ST)を1つ減らす間隔 [ms]
+    pub touch_keypad: Option<String>, // 画面上のキーパッド(off, beside, over)
+    pub scale: Option<String>,       // 拡大方法(integer, fit, stretch)
+    pub aspect_correction: Option<bool>, // 4:3 で表示する
+    pub fullscreen: Option<bool>,    // 起動時にフルスクリーンにする
+    pub osd: Option<bool>,           // FPS などの統計を表示する
+    pub filter: Option<String>,      // ちらつき対策(off, phosphor, blend, deflicker)
+    pub phosphor_half_life_ms: Option<u32>, // 残光の半減期 [ms]
+    pub blend_frames: Option<usize>, // blend で平均するフレーム数
+    pub upscale: Option<String>,     // ドット絵向けの拡大(none, scale2x, scale3x, eagle, xbr)
+    pub crt: Option<bool>,           // ブラウン管風の効果を起動時から有効にする
+    pub crt_effects: Option<Vec<String>>, // ブラウン管風の効果(scanlines, grid, bloom, vignette, curvature)
+    pub watch: Option<bool>,              // ROM イメージファイルの変更を監視して読み込み直す
+    pub keep_settings: Option<bool>,      // 読み込み直すときに実行中に変えた設定を全て引き継ぐ
+    pub fast_forward: Option<f32>,        // 早送り(Tab を押している間)の実行速度の倍率
+    pub screenshot_dir: Option<String>,   // スクリーンショット、動画を保存するディレクトリ
+    pub record_format: Option<String>,    // ホットキーで記録する動画の形式(gif, apng)
+    pub keymap: Option<BTreeMap<String, Vec<String>>>, // キー(0〜F)毎の割り当て(複数可)
+    pub gamepad_deadzone: Option<u16>,    // アナログスティックを押下とみなすしきい値(0〜32767)
+    pub gamepad: Option<BTreeMap<String, String>>, // ゲームパッドの入力(dpup, a, leftx- ...)毎のキー(0〜F)
+    pub palettes: Option<BTreeMap<String, Vec<String>>>, // 名前を付けたパレット("#rrggbb" を 2〜4色)
+}
+
+/// 設定ファイルの内容
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    pub base: Settings, // 共通の設定
+    #[serde(default)]
+    pub rom: BTreeMap<String, Settings>, // ROM 毎の設定
+}
+
+impl Config {
+    /// 既定の設定ファイル
+    ///
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// 設定ファイルを読み込む
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// ROM に対する設定(共通の設定に ROM 毎の節を重ねたもの)
+    ///
+    ///   rom_name: ROM のファイル名、rom_sha1: ROM イメージの SHA-1
+    pub fn for_rom(&self, rom_name: &str, rom_sha1: Option<&str>) -> Settings {
+        let mut settings = self.base.clone();
+        for key in [Some(rom_name), rom_sha1].into_iter().flatten() {
+            if let Some(section) = self.rom.get(key) {
+                settings.merge(section);
+            }
+        }
+        settings
+    }
+}
+
+impl Settings {
+    /// other で指定のある項目を上書きする
+    ///
+    pub fn merge(&mut self, other: &Settings) {
+        fn set<T: Clone>(dst: &mut Option<T>, src: &Option<T>) {
+            if src.is_some() {
+                dst.clone_from(src);
+            }
+        }
+        set(&mut self.platform, &other.platform);
+        set(&mut self.quirks, &other.quirks);
+        set(&mut self.tickrate, &other.tickrate);
+        set(&mut self.keypad, &other.keypad);
+        set(&mut self.keymap_preset, &other.keymap_preset);
+        set(&mut self.palette, &other.palette);
+        set(&mut self.fg_color, &other.fg_color);
+        set(&mut self.bg_color, &other.bg_color);
+        set(&mut self.cell_size, &other.cell_size);
+        set(&mut self.wait_ms, &other.wait_ms);
+        set(&mut self.cycle_ms, &other.cycle_ms);
+        set(&mut self.rom_dir, &other.rom_dir);
+        set(&mut self.touch_keypad, &other.touch_keypad);
+        set(&mut self.scale, &other.scale);
+        set(&mut self.aspect_correction, &other.aspect_correction);
+        set(&mut self.fullscreen, &other.fullscreen);
+        set(&mut self.osd, &other.osd);
+        set(&mut self.filter, &other.filter);
+        set(
+            &mut self.phosphor_half_life_ms,
+            &other.phosphor_half_life_ms,
+        );
+        set(&mut self.blend_frames, &other.blend_frames);
+        set(&mut self.upscale, &other.upscale);
+        set(&mut self.crt, &other.crt);
+        set(&mut self.crt_effects, &other.crt_effects);
+        set(&mut self.watch, &other.watch);
+        set(&mut self.keep_settings, &other.keep_settings);
+        set(&mut self.fast_forward, &other.fast_forward);
+        set(&mut self.screenshot_dir, &other.screenshot_dir);
+        set(&mut self.record_format, &other.record_format);
+        set(&mut self.gamepad_deadzone, &other.gamepad_deadzone);
+
+        // キー毎の割り当ては、指定のあるキーだけを置き換える
+        if let Some(keymap) = &other.keymap {
+            let dst = self.keymap.get_or_insert_with(BTreeMap::new);
+            for (key, names) in keymap.iter() {
+                dst.insert(key.to_uppercase(), names.clone());
+            }
+        }
+        if let Some(palettes) = &other.palettes {
+            let dst = self.palettes.get_or_insert_with(BTreeMap::new);
+            for (name, colors) in palettes.iter() {
+                dst.insert(name.clone(), colors.clone());
+            }
+        }
+        if let Some(gamepad) = &other.gamepad {
+            let dst = self.gamepad.get_or_insert_with(BTreeMap::new);
+            for (input, key) in gamepad.iter() {
+                dst.insert(input.clone(), key.clone());
+            }
+        }
+    }
+
+    /// TOML 形式の文字列
+    ///
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    /// プラットフォーム
+    ///
+    pub fn platform(&self) -> Result<Option<Platform>, String> {
+        self.platform
+            .as_ref()
+            .map(|name| {
+                Platform::from_name(name).ok_or(format!(
+                    "Unknown platform: '{}' (available: {})",
+                    name,
+                    Platform::NAMES.join(", ")
+                ))
+            })
+            .transpose()
+    }
+
+    /// 互換動作
+    ///
+    pub fn quirks(&self) -> Result<Option<Quirks>, String> {
+        self.quirks
+            .as_ref()
+            .map(|name| {
+                Quirks::from_profile(name).ok_or(format!(
+                    "Unknown quirks profile: '{}' (available: {})",
+                    name,
+                    Quirks::PROFILES.join(", ")
+                ))
+            })
+            .transpose()
+    }
+
+    /// キーボード上の 4x4 のキー
+    ///
+    pub fn keypad(&self) -> Result<Option<[Scancode; 16]>, String> {
+        let Some(names) = &self.keypad else {
+            return Ok(None);
+        };
+        if names.len() != 16 {
+            return Err(format!("keypad needs 16 keys (got {})", names.len()));
+        }
+        let mut keypad = [Scancode::Num1; 16];
+        for (dst, name) in keypad.iter_mut().zip(names.iter()) {
+            *dst = Scancode::from_name(name).ok_or(format!("Unknown key name: '{}'", name))?;
+        }
+        Ok(Some(keypad))
+    }
+
+    /// キーボード配列のプリセット
+    ///
+    pub fn keymap_preset(&self) -> Result<Option<[&'static str; 16]>, String> {
+        self.keymap_preset
+            .as_ref()
+            .map(|name| {
+                keymap::preset(name).ok_or(format!(
+                    "Unknown keymap preset: '{}' (available: {})",
+                    name,
+                    keymap::PRESETS.join(", ")
+                ))
+            })
+            .transpose()
+    }
+
+    /// パレット
+    ///
+    pub fn palette(&self) -> Result<Option<Palette>, String> {
+        self.palette
+            .as_ref()
+            .map(|name| Palette::find(name, self.palettes.as_ref().unwrap_or(&BTreeMap::new())))
+            .transpose()
+    }
+
+    /// 表示色 (背景色, ピクセルの色)
+    ///   片方だけの指定なら、もう片方は既定の色
+    pub fn colors(&self, default: (Color, Color)) -> Result<Option<(Color, Color)>, String> {
+        if self.bg_color.is_none() && self.fg_color.is_none() {
+            return Ok(None);
+        }
+        let color = |s: &Option<String>, default| match s {
+            Some(s) => parse_color(s).ok_or(format!("Invalid colour: '{}' (use #rrggbb)", s)),
+            None => Ok(default),
+        };
+        Ok(Some((
+            color(&self.bg_color, default.0)?,
+            color(&self.fg_color, default.1)?,
+        )))
+    }
+}
+
+/// 設定ファイルなどを置くディレクトリ
+///   $XDG_CONFIG_HOME/chip8rs (既定は ~/.config/chip8rs)
+pub fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("chip8rs"))
+}
+
+/// "#rrggbb" 形式の色
+///
+pub fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// 色を "#rrggbb" 形式にする
+///
+pub fn format_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "1ba58656810b67fd131eb9af3e3987863bf26c90";
+
+    fn config() -> Config {
+        toml::from_str(&format!(
+            r#"
+            tickrate = 10
+            palette = "amber"
+            scale = "fit"
+            osd = true
+            keymap = {{ "a" = ["Z"], "B" = ["X"] }}
+
+            [rom."pong.ch8"]
+            tickrate = 20
+            palette = "green"
+
+            [rom."{}"]
+            tickrate = 30
+            "#,
+            SHA1
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn rom_section_by_name_and_sha1() {
+        let config = config();
+
+        // 節の無い ROM は共通の設定のまま
+        let settings = config.for_rom("other.ch8", None);
+        assert_eq!(settings.tickrate, Some(10));
+        assert_eq!(settings.palette.as_deref(), Some("amber"));
+
+        // ファイル名の節
+        let settings = config.for_rom("pong.ch8", None);
+        assert_eq!(settings.tickrate, Some(20));
+        assert_eq!(settings.palette.as_deref(), Some("green"));
+
+        // SHA-1 の節(ファイル名が違っても当てはまる)
+        let settings = config.for_rom("renamed.ch8", Some(SHA1));
+        assert_eq!(settings.tickrate, Some(30));
+        assert_eq!(settings.palette.as_deref(), Some("amber"));
+
+        // 両方当てはまれば SHA-1 の節を後から重ねる
+        let settings = config.for_rom("pong.ch8", Some(SHA1));
+        assert_eq!(settings.tickrate, Some(30));
+        assert_eq!(settings.palette.as_deref(), Some("green"));
+    }
+
+    #[test]
+    fn merge_precedence() {
+        // 既定値 < 設定ファイル < ROM 毎の節 < コマンドライン < 実行中の変更
+        let mut settings = Settings::default();
+        settings.merge(&config().for_rom("pong.ch8", None));
+        assert_eq!(settings.tickrate, Some(20));
+
+        let cli = Settings {
+            palette: Some("blue".to_string()),
+            scale: Some("integer".to_string()),
+            keymap: Some(BTreeMap::from([("b".to_string(), vec!["C".to_string()])])),
+            ..Default::default()
+        };
+        settings.merge(&cli);
+
+        let runtime = Settings {
+            scale: Some("stretch".to_string()),
+            ..Default::default()
+        };
+        settings.merge(&runtime);
+
+        assert_eq!(settings.tickrate, Some(20)); // ROM 毎の節
+        assert_eq!(settings.palette.as_deref(), Some("blue")); // コマンドライン
+        assert_eq!(settings.scale.as_deref(), Some("stretch")); // 実行中の変更
+        assert_eq!(settings.osd, Some(true)); // 設定ファイル
+        assert_eq!(settings.fullscreen, None); // 既定値
+
+        // キー毎の割り当ては指定のあるキーだけ置き換える(キー名は大文字にそろえる)
+        assert_eq!(
+            settings.keymap,
+            Some(BTreeMap::from([
+                ("A".to_string(), vec!["Z".to_string()]),
+                ("B".to_string(), vec!["C".to_string()]),
+            ]))
+        );
+    }
+}
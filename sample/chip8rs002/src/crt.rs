@@ -0,0 +1,178 @@
+//
+// ブラウン管風の表示効果(ソフトウェア処理)
+//
+//   拡大後の画像を CPU で作るので、GPU の無い環境(ヘッドレスの Linux、Raspberry Pi など)でも使える。
+//     scanlines: 走査線(1ラインの上下を暗くする)
+//     grid     : 画素の間の隙間
+//     bloom    : 明るい画素の周りのにじみ
+//     vignette : 周辺を暗くする
+//     curvature: 画面の湾曲
+//
+
+/// 効果名一覧
+pub const NAMES: [&str; 5] = ["scanlines", "grid", "bloom", "vignette", "curvature"];
+
+/// 既定の効果
+pub const DEFAULT_EFFECTS: [&str; 3] = ["scanlines", "bloom", "vignette"];
+
+const SCANLINE_DEPTH: f32 = 0.4; // 走査線の暗さ
+const GRID_GAP: f32 = 0.12; // 画素の隙間の幅(画素の大きさに対する比率)
+const GRID_DEPTH: f32 = 0.5; // 隙間の暗さ
+const BLOOM_STRENGTH: f32 = 0.35; // にじみの強さ
+const VIGNETTE_DEPTH: f32 = 0.3; // 周辺の暗さ
+const CURVATURE: f32 = 0.08; // 湾曲の強さ
+const MIN_CELL: f32 = 3.0; // 走査線と隙間を描く最小の画素の大きさ [ピクセル]
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrtEffects {
+    pub enabled: bool,   // 効果を有効にする
+    pub scanlines: bool, // 走査線
+    pub grid: bool,      // 画素の隙間
+    pub bloom: bool,     // にじみ
+    pub vignette: bool,  // 周辺減光
+    pub curvature: bool, // 湾曲
+}
+
+impl CrtEffects {
+    /// 効果名の一覧から作る
+    ///
+    pub fn from_names<S: AsRef<str>>(names: &[S], enabled: bool) -> Result<CrtEffects, String> {
+        let mut effects = CrtEffects {
+            enabled,
+            ..Default::default()
+        };
+        for name in names.iter() {
+            match name.as_ref() {
+                "scanlines" => effects.scanlines = true,
+                "grid" => effects.grid = true,
+                "bloom" => effects.bloom = true,
+                "vignette" => effects.vignette = true,
+                "curvature" => effects.curvature = true,
+                name => {
+                    return Err(format!(
+                        "Unknown CRT effect: '{}' (available: {})",
+                        name,
+                        NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(effects)
+    }
+
+    /// 有効な効果名の一覧
+    ///
+    pub fn names(&self) -> Vec<String> {
+        let flags = [
+            self.scanlines,
+            self.grid,
+            self.bloom,
+            self.vignette,
+            self.curvature,
+        ];
+        NAMES
+            .iter()
+            .zip(flags.iter())
+            .filter(|(_, &on)| on)
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// src (RGB24, src_size) を dst_size に拡大して効果をかけ、dst (RGB24) に書き込む
+    ///
+    pub fn process(
+        &self,
+        src: &[u8],
+        src_size: (usize, usize),
+        dst: &mut Vec<u8>,
+        dst_size: (usize, usize),
+    ) {
+        let (src_w, src_h) = src_size;
+        let (dst_w, dst_h) = dst_size;
+        dst.clear();
+        dst.resize(dst_w * dst_h * 3, 0);
+        if src_w == 0 || src_h == 0 || src.len() < src_w * src_h * 3 {
+            return;
+        }
+
+        // にじみは元の解像度でぼかした画像を足す
+        let glow = if self.bloom {
+            blur(src, src_w, src_h)
+        } else {
+            Vec::new()
+        };
+
+        // 1画素の大きさが小さいときは走査線と隙間を描かない
+        let cell_w = dst_w as f32 / src_w as f32;
+        let cell_h = dst_h as f32 / src_h as f32;
+        let scanlines = self.scanlines && cell_h >= MIN_CELL;
+        let grid = self.grid && cell_w.min(cell_h) >= MIN_CELL;
+
+        for y in 0..dst_h {
+            for x in 0..dst_w {
+                // 画面の中心を原点とした -1.0〜1.0 の座標
+                let mut u = (x as f32 + 0.5) / dst_w as f32 * 2.0 - 1.0;
+                let mut v = (y as f32 + 0.5) / dst_h as f32 * 2.0 - 1.0;
+                if self.curvature {
+                    (u, v) = (u * (1.0 + CURVATURE * v * v), v * (1.0 + CURVATURE * u * u));
+                    if u.abs() > 1.0 || v.abs() > 1.0 {
+                        continue;
+                    }
+                }
+
+                // 元の画像の位置と、画素の中での位置(0.0〜1.0)
+                let sx = (u + 1.0) / 2.0 * src_w as f32;
+                let sy = (v + 1.0) / 2.0 * src_h as f32;
+                let (ix, iy) = ((sx as usize).min(src_w - 1), (sy as usize).min(src_h - 1));
+                let (fx, fy) = (sx.fract(), sy.fract());
+
+                let mut gain = 1.0;
+                if scanlines {
+                    gain *= 1.0 - SCANLINE_DEPTH * (1.0 - (fy * std::f32::consts::PI).sin());
+                }
+                if grid && (fx < GRID_GAP || fy < GRID_GAP) {
+                    gain *= 1.0 - GRID_DEPTH;
+                }
+                if self.vignette {
+                    gain *= 1.0 - VIGNETTE_DEPTH * (u * u + v * v) / 2.0;
+                }
+
+                let i = (iy * src_w + ix) * 3;
+                let o = (y * dst_w + x) * 3;
+                for c in 0..3 {
+                    let mut value = src[i + c] as f32 * gain;
+                    if self.bloom {
+                        value += glow[i + c] * BLOOM_STRENGTH;
+                    }
+                    dst[o + c] = value.clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// 3x3 の平均でぼかす
+///
+fn blur(src: &[u8], width: usize, height: usize) -> Vec<f32> {
+    let mut out = vec![0.0; width * height * 3];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0_f32; 3];
+            let mut count = 0.0;
+            for yy in y.saturating_sub(1)..(y + 2).min(height) {
+                for xx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let i = (yy * width + xx) * 3;
+                    for (c, s) in sum.iter_mut().enumerate() {
+                        *s += src[i + c] as f32;
+                    }
+                    count += 1.0;
+                }
+            }
+            let o = (y * width + x) * 3;
+            for (c, s) in sum.iter().enumerate() {
+                out[o + c] = s / count;
+            }
+        }
+    }
+    out
+}
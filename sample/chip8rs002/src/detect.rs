@@ -0,0 +1,297 @@
+//
+// ROMイメージの静的な解析によるプラットフォーム推定
+//
+//   ROM データベースに無い ROM について、特定の派生仕様でしか使わない命令や、
+//   ジャンプ先から分かる読み込みアドレス、互換動作に依存した命令の並びを探し、
+//   最も当てはまりそうなプラットフォームと互換動作を選ぶ。
+//   命令とデータの区別はつかないので、2バイト境界毎の全ての語を命令とみなして数える。
+//
+
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+
+/// 推定結果
+pub struct Detection {
+    pub platform: Platform,   // 推定したプラットフォーム
+    pub quirks: Quirks,       // 推定した互換動作
+    pub reasons: Vec<String>, // 推定の根拠
+}
+
+/// 見つかった特徴的な命令の数
+#[derive(Default)]
+struct Counts {
+    schip: usize,      // SCHIP 命令(00Cn, 00FB〜00FF, Dxy0, Fx30)
+    rpl: usize,        // RPL フラグ(Fx75, Fx85)
+    xochip: usize,     // XO-CHIP 命令(F000 nnnn, Fn01, 5xy2, 5xy3, F002)
+    megachip: usize,   // MegaChip 命令(0011)
+    chip8x: usize,     // CHIP-8X 命令(02A0)
+    hires: usize,      // hi-res CHIP-8 命令(0230)
+    shift_vy: usize,   // Vy を使うシフト(8xy6/8xyE で y が x とも 0 とも違う)
+    shift_vx: usize,   // Vx 自身のシフト(8x06/8x0E)
+    load_store: usize, // I を進める前提の連続した Fx55/Fx65
+}
+
+/// ROMイメージを解析してプラットフォームと互換動作を推定する
+///
+pub fn scan(rom: &[u8]) -> Detection {
+    let words: Vec<u16> = rom
+        .chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)]))
+        .collect();
+
+    let mut counts = Counts::default();
+    let mut last_mem_op: Option<usize> = None; // 直前の Fx55/Fx65 の位置
+    for (i, &op) in words.iter().enumerate() {
+        let x = (op >> 8) & 0xF;
+        let y = (op >> 4) & 0xF;
+        match op {
+            0x00C0..=0x00CF | 0x00FB..=0x00FF => counts.schip += 1,
+            0x0011 => counts.megachip += 1,
+            0x02A0 => counts.chip8x += 1,
+            0x0230 => counts.hires += 1,
+            0xF000 | 0xF002 => counts.xochip += 1,
+            _ => match (op >> 12, op & 0xFF) {
+                (0xD, 0x00) => counts.schip += 1,
+                (0xF, 0x30) => counts.schip += 1,
+                (0xF, 0x75) | (0xF, 0x85) => counts.rpl += 1,
+                (0xF, 0x01) => counts.xochip += 1,
+                (0x5, _) if op & 0xF == 0x2 || op & 0xF == 0x3 => counts.xochip += 1,
+                (0x8, _) if op & 0xF == 0x6 || op & 0xF == 0xE => {
+                    if y == 0 {
+                        counts.shift_vx += 1;
+                    } else if x != y {
+                        counts.shift_vy += 1;
+                    }
+                }
+                (0xF, 0x55) | (0xF, 0x65) => {
+                    // I を設定し直さずに続けて読み書きしていれば、I が進む前提
+                    if last_mem_op.is_some_and(|prev| i - prev <= 4) {
+                        counts.load_store += 1;
+                    }
+                    last_mem_op = Some(i);
+                }
+                // I を設定する命令
+                (0xA, _) | (0xF, 0x1E) | (0xF, 0x29) | (0xF, 0x33) => last_mem_op = None,
+                _ => {}
+            },
+        }
+    }
+
+    let mut reasons = Vec::new();
+    let base = guess_load_address(&words, rom.len(), &mut reasons);
+
+    // プラットフォーム
+    let platform = if counts.megachip > 0 {
+        reasons.push(format!("0011 (MegaChip mode) x{}", counts.megachip));
+        Platform::MegaChip8
+    } else if counts.chip8x > 0 || base == 0x300 {
+        if counts.chip8x > 0 {
+            reasons.push(format!(
+                "02A0 (CHIP-8X background colour) x{}",
+                counts.chip8x
+            ));
+        }
+        Platform::Chip8X
+    } else if words.first() == Some(&0x1260) {
+        reasons.push("starts with 1260 (hi-res CHIP-8 entry)".to_string());
+        if counts.hires > 0 {
+            reasons.push(format!("0230 (hi-res clear) x{}", counts.hires));
+        }
+        Platform::HiRes
+    } else if base == 0x600 {
+        Platform::Eti660
+    } else {
+        Platform::Chip8
+    };
+
+    // 対応していない派生仕様の命令(現代的なインタプリタ向けの ROM と判断する)
+    let modern = counts.schip + counts.rpl + counts.xochip > 0;
+    if counts.schip > 0 {
+        reasons.push(format!(
+            "SCHIP instructions (00Cn/00FB-00FF/Dxy0/Fx30) x{} (not supported)",
+            counts.schip
+        ));
+    }
+    if counts.rpl > 0 {
+        reasons.push(format!(
+            "Fx75/Fx85 (RPL flags) x{} (not supported)",
+            counts.rpl
+        ));
+    }
+    if counts.xochip > 0 {
+        reasons.push(format!(
+            "XO-CHIP instructions (F000 nnnn/Fn01/5xy2/5xy3) x{} (not supported)",
+            counts.xochip
+        ));
+    }
+
+    // 互換動作
+    let mut quirks = platform.default_quirks();
+    if modern {
+        quirks = Quirks {
+            memory_increment: MemoryIncrement::Unchanged,
+            ..Quirks::default()
+        };
+        reasons.push("modern interpreter quirks for SCHIP/XO-CHIP era ROM".to_string());
+    }
+    if counts.shift_vy > counts.shift_vx {
+        quirks.shift_vy = true;
+        reasons.push(format!(
+            "8xy6/8xyE with distinct Vy x{} (shift uses Vy)",
+            counts.shift_vy
+        ));
+    } else if counts.shift_vx > 0 {
+        quirks.shift_vy = false;
+        reasons.push(format!(
+            "8x06/8x0E x{} (shift uses Vx only)",
+            counts.shift_vx
+        ));
+    }
+    if counts.load_store > 0 && !modern {
+        quirks.memory_increment = MemoryIncrement::Both;
+        reasons.push(format!(
+            "consecutive Fx55/Fx65 without reloading I x{} (I increments)",
+            counts.load_store
+        ));
+    }
+
+    Detection {
+        platform,
+        quirks,
+        reasons,
+    }
+}
+
+/// ジャンプ(1nnn)、サブルーチン呼び出し(2nnn)、Annn の行き先が
+/// ROM の範囲に最も多く収まる読み込みアドレスを選ぶ
+///
+fn guess_load_address(words: &[u16], len: usize, reasons: &mut Vec<String>) -> usize {
+    let targets: Vec<usize> = words
+        .iter()
+        .filter(|&&op| matches!(op >> 12, 0x1 | 0x2 | 0xA))
+        .map(|&op| (op & 0xFFF) as usize)
+        .collect();
+    if targets.is_empty() {
+        return 0x200;
+    }
+
+    let score = |base: usize| {
+        targets
+            .iter()
+            .filter(|&&t| (base..base + len).contains(&t))
+            .count()
+    };
+
+    // 同点なら 0x200 を優先
+    let mut best = 0x200;
+    // (CHIP-8X 0x300、ETI-660 0x600)
+    for base in [0x300, 0x600] {
+        if score(base) > score(best) {
+            best = base;
+        }
+    }
+    if best != 0x200 {
+        reasons.push(format!(
+            "{}/{} jump targets fit a load address of 0x{:03X} (0x200: {})",
+            score(best),
+            targets.len(),
+            best,
+            score(0x200)
+        ));
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 命令の並びから ROM イメージを組み立てる
+    fn rom(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn platform_instructions() {
+        // 0011 (MegaChip モード ON)
+        let detection = scan(&rom(&[0x00E0, 0x0011, 0x1204]));
+        assert_eq!(detection.platform, Platform::MegaChip8);
+
+        // 02A0 (CHIP-8X 背景色の切り替え)
+        let detection = scan(&rom(&[0x00E0, 0x02A0, 0x1204]));
+        assert_eq!(detection.platform, Platform::Chip8X);
+
+        // 先頭の 1260 (hi-res CHIP-8 の入口)
+        let detection = scan(&rom(&[0x1260, 0x0230, 0x1202]));
+        assert_eq!(detection.platform, Platform::HiRes);
+        assert_eq!(detection.quirks, Quirks::from_profile("vip").unwrap());
+
+        // どれも無ければ CHIP-8
+        let detection = scan(&rom(&[0x00E0, 0x6001, 0x1204]));
+        assert_eq!(detection.platform, Platform::Chip8);
+        assert!(detection.reasons.is_empty());
+    }
+
+    #[test]
+    fn load_address_from_jump_targets() {
+        // 0x300 から読み込むとジャンプ先が ROM に収まる
+        let words = [0x6000, 0x6100, 0x1304, 0x2306, 0xA308];
+        let mut reasons = Vec::new();
+        assert_eq!(guess_load_address(&words, 10, &mut reasons), 0x300);
+        assert_eq!(reasons.len(), 1);
+        assert_eq!(scan(&rom(&words)).platform, Platform::Chip8X);
+
+        // 0x600 (ETI-660)
+        let words = [0x6000, 0x6100, 0x1604, 0x2606, 0xA608];
+        assert_eq!(guess_load_address(&words, 10, &mut Vec::new()), 0x600);
+        assert_eq!(scan(&rom(&words)).platform, Platform::Eti660);
+
+        // 同点なら 0x200
+        let words = [0x1202, 0x1302];
+        assert_eq!(guess_load_address(&words, 4, &mut Vec::new()), 0x200);
+
+        // ジャンプが無ければ 0x200
+        assert_eq!(guess_load_address(&[0x6000], 2, &mut Vec::new()), 0x200);
+    }
+
+    #[test]
+    fn shift_quirk() {
+        // Vx と違う Vy を使うシフト
+        let detection = scan(&rom(&[0x8126, 0x823E, 0x1204]));
+        assert!(detection.quirks.shift_vy);
+
+        // Vy が 0 のシフトは Vx 自身のシフトとみなす
+        let detection = scan(&rom(&[0x8106, 0x820E, 0x1204]));
+        assert!(!detection.quirks.shift_vy);
+
+        // x == y はどちらとも言えない
+        let detection = scan(&rom(&[0x8116, 0x1202]));
+        assert!(!detection.quirks.shift_vy);
+        assert!(detection.reasons.is_empty());
+    }
+
+    #[test]
+    fn memory_increment_quirk() {
+        // I を設定し直さずに続けて Fx55/Fx65
+        let detection = scan(&rom(&[0xA206, 0xF255, 0xF265, 0x1206]));
+        assert_eq!(detection.quirks.memory_increment, MemoryIncrement::Both);
+
+        // 間で I を設定し直していれば I が進む前提ではない
+        let detection = scan(&rom(&[0xA208, 0xF255, 0xA208, 0xF265, 0x1208]));
+        assert_eq!(
+            detection.quirks.memory_increment,
+            Quirks::default().memory_increment
+        );
+    }
+
+    #[test]
+    fn odd_length_padding() {
+        // 最後の1バイトは下位を 0 で補う(00FF ではなく FF00、00C5 ではなく C500)
+        for last in [0xFF, 0xC5] {
+            let detection = scan(&[0x60, 0x00, 0x12, 0x00, last]);
+            assert_eq!(detection.platform, Platform::Chip8);
+            assert_eq!(detection.quirks, Quirks::default());
+            assert!(detection.reasons.is_empty());
+        }
+    }
+}
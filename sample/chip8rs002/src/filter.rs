@@ -0,0 +1,241 @@
+//
+// 表示のちらつき対策
+//
+//   CHIP-8 のゲームはスプライトを XOR で消しては描き直すので、フレーム毎に表示するとちらつく。
+//   表示する画像(RGB)だけに次のフィルタをかける(vram の内容は変えない)。
+//     phosphor : 画素が消えるときは、半減期に従ってゆっくり背景色に戻す(ブラウン管の残光)
+//     blend    : 直近 N フレームを平均する
+//     deflicker: 直近 2 フレームのうち背景色から遠い方を表示する(2 フレームの OR)
+//   点灯している画素が背景より明るいとは限らない(明るい背景に暗い画素のパレットもある)ので、
+//   明るさではなく、画素ごとの背景色からの離れ具合で比べる。
+//
+
+use std::collections::VecDeque;
+
+use sdl2::pixels::Color;
+
+/// フィルタの種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterMode {
+    Off,
+    Phosphor,
+    Blend,
+    Deflicker,
+}
+
+impl FilterMode {
+    /// フィルタ名一覧
+    pub const NAMES: [&'static str; 4] = ["off", "phosphor", "blend", "deflicker"];
+
+    /// 名前から取得
+    ///
+    pub fn from_name(name: &str) -> Option<FilterMode> {
+        match name {
+            "off" => Some(FilterMode::Off),
+            "phosphor" => Some(FilterMode::Phosphor),
+            "blend" => Some(FilterMode::Blend),
+            "deflicker" => Some(FilterMode::Deflicker),
+            _ => None,
+        }
+    }
+
+    /// 名前
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterMode::Off => "off",
+            FilterMode::Phosphor => "phosphor",
+            FilterMode::Blend => "blend",
+            FilterMode::Deflicker => "deflicker",
+        }
+    }
+
+    /// 次のフィルタ(実行中の切り替え用)
+    ///
+    pub fn next(&self) -> FilterMode {
+        match self {
+            FilterMode::Off => FilterMode::Phosphor,
+            FilterMode::Phosphor => FilterMode::Blend,
+            FilterMode::Blend => FilterMode::Deflicker,
+            FilterMode::Deflicker => FilterMode::Off,
+        }
+    }
+}
+
+/// 残光の既定の半減期 [ms]
+pub const DEFAULT_HALF_LIFE_MS: f32 = 50.0;
+
+/// 平均する既定のフレーム数
+pub const DEFAULT_BLEND_FRAMES: usize = 3;
+
+pub struct DisplayFilter {
+    mode: FilterMode,           // フィルタの種類
+    decay: f32,                 // 1フレームあたりの残光の減衰率
+    blend_frames: usize,        // 平均するフレーム数
+    history: VecDeque<Vec<u8>>, // 直近のフレーム(新しい順)
+    glow: Vec<f32>,             // 残光(背景色との差)
+    background: [u8; 3],        // 履歴を取ったときの背景色
+}
+
+impl DisplayFilter {
+    /// half_life_ms: 残光の半減期、blend_frames: 平均するフレーム数、frame_ms: 1フレームの時間
+    ///
+    pub fn new(
+        mode: FilterMode,
+        half_life_ms: f32,
+        blend_frames: usize,
+        frame_ms: f32,
+    ) -> DisplayFilter {
+        DisplayFilter {
+            mode,
+            decay: 0.5_f32.powf(frame_ms / half_life_ms.max(1.0)),
+            blend_frames: blend_frames.max(1),
+            history: VecDeque::new(),
+            glow: Vec::new(),
+            background: [0; 3],
+        }
+    }
+
+    /// フィルタの種類
+    ///
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    /// フィルタの種類を変える(それまでの履歴は捨てる)
+    ///
+    pub fn set_mode(&mut self, mode: FilterMode) {
+        self.mode = mode;
+        self.history.clear();
+        self.glow.clear();
+    }
+
+    /// フレーム(RGB)にフィルタをかける
+    ///
+    ///   background: 背景色(消えている画素の色)
+    pub fn apply(&mut self, frame: &mut [u8], background: Color) {
+        // 解像度か背景色が変わったら履歴を捨てる
+        let bg = [background.r, background.g, background.b];
+        if self.background != bg {
+            self.background = bg;
+            self.history.clear();
+            self.glow.clear();
+        }
+        if self.history.front().is_some_and(|f| f.len() != frame.len()) {
+            self.history.clear();
+        }
+        if self.glow.len() != frame.len() {
+            self.glow.clear();
+        }
+
+        match self.mode {
+            FilterMode::Off => {}
+            FilterMode::Phosphor => {
+                if self.glow.is_empty() {
+                    self.glow = frame
+                        .chunks_exact(3)
+                        .flat_map(|p| [0, 1, 2].map(|c| p[c] as f32 - bg[c] as f32))
+                        .collect();
+                }
+                for (pixel, glow) in frame.chunks_exact_mut(3).zip(self.glow.chunks_exact_mut(3)) {
+                    // 点くときはすぐに、消えるときはゆっくり背景色に戻す
+                    let current = [0, 1, 2].map(|c| pixel[c] as f32 - bg[c] as f32);
+                    let decayed = [0, 1, 2].map(|c| glow[c] * self.decay);
+                    let next = if strength(&current) >= strength(&decayed) {
+                        current
+                    } else {
+                        decayed
+                    };
+                    for c in 0..3 {
+                        glow[c] = next[c];
+                        pixel[c] = (bg[c] as f32 + next[c]).round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+            FilterMode::Blend => {
+                self.history.push_front(frame.to_vec());
+                self.history.truncate(self.blend_frames);
+                let count = self.history.len() as u32;
+                for (i, v) in frame.iter_mut().enumerate() {
+                    let sum: u32 = self.history.iter().map(|f| f[i] as u32).sum();
+                    *v = (sum / count) as u8;
+                }
+            }
+            FilterMode::Deflicker => {
+                let current = frame.to_vec();
+                if let Some(prev) = self.history.front() {
+                    let offset = |p: &[u8]| [0, 1, 2].map(|c| p[c] as f32 - bg[c] as f32);
+                    for (pixel, prev) in frame.chunks_exact_mut(3).zip(prev.chunks_exact(3)) {
+                        if strength(&offset(prev)) > strength(&offset(pixel)) {
+                            pixel.copy_from_slice(prev);
+                        }
+                    }
+                }
+                self.history.clear();
+                self.history.push_front(current);
+            }
+        }
+    }
+}
+
+/// 背景色からの離れ具合(各色の差の絶対値の和)
+///
+fn strength(offset: &[f32; 3]) -> f32 {
+    offset.iter().map(|d| d.abs()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 明るい背景に暗い画素のパレット(lcd)
+    const BG: Color = Color::RGB(155, 188, 15);
+    const FG: Color = Color::RGB(15, 56, 15);
+
+    fn pixel(color: Color) -> Vec<u8> {
+        vec![color.r, color.g, color.b]
+    }
+
+    #[test]
+    fn phosphor_fades_to_background() {
+        let mut filter = DisplayFilter::new(FilterMode::Phosphor, 16.0, 3, 16.0);
+        let mut frame = pixel(FG);
+        filter.apply(&mut frame, BG);
+        assert_eq!(frame, pixel(FG));
+
+        // 消えた画素は前景色と背景色の中間(半減期 = 1フレーム)
+        let mut frame = pixel(BG);
+        filter.apply(&mut frame, BG);
+        assert_eq!(frame, vec![85, 122, 15]);
+
+        // 点いた画素はすぐに前景色になる
+        let mut frame = pixel(FG);
+        filter.apply(&mut frame, BG);
+        assert_eq!(frame, pixel(FG));
+    }
+
+    #[test]
+    fn deflicker_keeps_lit_pixel() {
+        let mut filter = DisplayFilter::new(FilterMode::Deflicker, 50.0, 3, 16.0);
+        let mut frame = [pixel(FG), pixel(BG)].concat();
+        filter.apply(&mut frame, BG);
+
+        // 前のフレームで点いていた画素は、背景色が明るくても点いたまま
+        let mut frame = [pixel(BG), pixel(BG)].concat();
+        filter.apply(&mut frame, BG);
+        assert_eq!(frame, [pixel(FG), pixel(BG)].concat());
+    }
+
+    #[test]
+    fn background_change_resets_history() {
+        let mut filter = DisplayFilter::new(FilterMode::Deflicker, 50.0, 3, 16.0);
+        let mut frame = pixel(FG);
+        filter.apply(&mut frame, BG);
+
+        // パレットを変えたら、前の色は残さない
+        let black = Color::RGB(0, 0, 0);
+        let mut frame = pixel(black);
+        filter.apply(&mut frame, black);
+        assert_eq!(frame, pixel(black));
+    }
+}
@@ -0,0 +1,130 @@
+//
+// 画面表示用の 3x5 ドットのビットマップフォント
+//
+//   SDL2_ttf を使わずに文字を描くための組み込みフォント。
+//   英小文字は大文字で表示し、フォントに無い文字は '?' で表示する。
+//
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+/// 文字の横ドット数
+pub const GLYPH_W: u32 = 3;
+/// 文字の縦ドット数
+pub const GLYPH_H: u32 = 5;
+/// 1文字あたりの送り(文字間 1ドット)
+pub const ADVANCE: u32 = GLYPH_W + 1;
+/// 1行あたりの送り(行間 2ドット)
+pub const LINE_HEIGHT: u32 = GLYPH_H + 2;
+
+/// 文字のパターン(上の行から 3bit ずつ、左のドットが上位ビット)
+const GLYPHS: [(char, u16); 64] = [
+    (' ', 0b000_000_000_000_000),
+    ('0', 0b111_101_101_101_111),
+    ('1', 0b010_110_010_010_111),
+    ('2', 0b111_001_111_100_111),
+    ('3', 0b111_001_111_001_111),
+    ('4', 0b101_101_111_001_001),
+    ('5', 0b111_100_111_001_111),
+    ('6', 0b111_100_111_101_111),
+    ('7', 0b111_001_001_010_010),
+    ('8', 0b111_101_111_101_111),
+    ('9', 0b111_101_111_001_111),
+    ('A', 0b010_101_111_101_101),
+    ('B', 0b110_101_110_101_110),
+    ('C', 0b011_100_100_100_011),
+    ('D', 0b110_101_101_101_110),
+    ('E', 0b111_100_110_100_111),
+    ('F', 0b111_100_110_100_100),
+    ('G', 0b011_100_101_101_011),
+    ('H', 0b101_101_111_101_101),
+    ('I', 0b111_010_010_010_111),
+    ('J', 0b001_001_001_101_010),
+    ('K', 0b101_101_110_101_101),
+    ('L', 0b100_100_100_100_111),
+    ('M', 0b101_111_111_101_101),
+    ('N', 0b110_101_101_101_101),
+    ('O', 0b010_101_101_101_010),
+    ('P', 0b110_101_110_100_100),
+    ('Q', 0b010_101_101_110_011),
+    ('R', 0b110_101_110_101_101),
+    ('S', 0b011_100_010_001_110),
+    ('T', 0b111_010_010_010_010),
+    ('U', 0b101_101_101_101_111),
+    ('V', 0b101_101_101_101_010),
+    ('W', 0b101_101_111_111_101),
+    ('X', 0b101_101_010_101_101),
+    ('Y', 0b101_101_010_010_010),
+    ('Z', 0b111_001_010_100_111),
+    ('.', 0b000_000_000_000_010),
+    (',', 0b000_000_000_010_100),
+    (':', 0b000_010_000_010_000),
+    (';', 0b000_010_000_010_100),
+    ('!', 0b010_010_010_000_010),
+    ('?', 0b111_001_010_000_010),
+    ('-', 0b000_000_111_000_000),
+    ('+', 0b000_010_111_010_000),
+    ('/', 0b001_001_010_100_100),
+    ('\\', 0b100_100_010_001_001),
+    ('(', 0b001_010_010_010_001),
+    (')', 0b100_010_010_010_100),
+    ('[', 0b011_010_010_010_011),
+    (']', 0b110_010_010_010_110),
+    ('%', 0b101_001_010_100_101),
+    ('=', 0b000_111_000_111_000),
+    ('\'', 0b010_010_000_000_000),
+    ('"', 0b101_101_000_000_000),
+    ('_', 0b000_000_000_000_111),
+    ('<', 0b001_010_100_010_001),
+    ('>', 0b100_010_001_010_100),
+    ('#', 0b101_111_101_111_101),
+    ('*', 0b000_101_010_101_000),
+    ('|', 0b010_010_010_010_010),
+    ('@', 0b111_101_111_100_011),
+    ('&', 0b010_101_010_101_011),
+    ('^', 0b010_101_000_000_000),
+];
+
+/// 文字のパターン
+///
+fn glyph(c: char) -> u16 {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|(g, _)| *g == c)
+        .or_else(|| GLYPHS.iter().find(|(g, _)| *g == '?'))
+        .map_or(0, |&(_, bits)| bits)
+}
+
+/// 文字列を描いたときの大きさ (横, 縦) [ピクセル]
+///
+///   dot: 1ドットの大きさ [ピクセル]
+pub fn text_size(text: &str, dot: u32) -> (u32, u32) {
+    let count = text.chars().count() as u32;
+    ((count * ADVANCE).saturating_sub(1) * dot, GLYPH_H * dot)
+}
+
+/// (x, y) を左上にして文字列を描く
+///
+pub fn draw_text(canvas: &mut Canvas<Window>, x: i32, y: i32, dot: u32, text: &str, color: Color) {
+    canvas.set_draw_color(color);
+    for (n, c) in text.chars().enumerate() {
+        let bits = glyph(c);
+        let cx = x + (n as u32 * ADVANCE * dot) as i32;
+        for row in 0..GLYPH_H {
+            for col in 0..GLYPH_W {
+                let bit = 1 << ((GLYPH_H - 1 - row) * GLYPH_W + (GLYPH_W - 1 - col));
+                if bits & bit != 0 {
+                    let _ = canvas.fill_rect(Rect::new(
+                        cx + (col * dot) as i32,
+                        y + (row * dot) as i32,
+                        dot,
+                        dot,
+                    ));
+                }
+            }
+        }
+    }
+}
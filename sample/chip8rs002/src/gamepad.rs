@@ -0,0 +1,176 @@
+//
+// ゲームパッド入力(SDL GameController)
+//
+//   ボタンやアナログスティックの傾きをキーパッドのキー値に割り当てる。
+//   割り当ての名前は SDL のボタン名(a, b, x, y, start, dpup ...)と、
+//   アナログスティックの軸名に傾きの向きを付けたもの(leftx-, lefty+ ...)。
+//   接続/切断はイベントで受け取るので、実行中に抜き差しできる。
+//
+
+use std::collections::BTreeMap;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use crate::keymap;
+
+/// 既定の割り当て(十字キーと左スティックで 5/7/8/9、A で 6、B で 4)
+const DEFAULT_MAPPING: [(&str, u8); 11] = [
+    ("dpup", 0x5),
+    ("dpdown", 0x8),
+    ("dpleft", 0x7),
+    ("dpright", 0x9),
+    ("lefty-", 0x5),
+    ("lefty+", 0x8),
+    ("leftx-", 0x7),
+    ("leftx+", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+    ("start", 0xF),
+];
+
+/// ROM データベースの操作名(keys)に対応する入力
+const DB_INPUTS: [(&str, &[&str]); 6] = [
+    ("up", &["dpup", "lefty-"]),
+    ("down", &["dpdown", "lefty+"]),
+    ("left", &["dpleft", "leftx-"]),
+    ("right", &["dpright", "leftx+"]),
+    ("a", &["a"]),
+    ("b", &["b"]),
+];
+
+/// アナログスティックの傾きを押下とみなす既定のしきい値
+pub const DEFAULT_DEADZONE: i16 = 16000;
+
+/// ゲームパッドの入力
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Button(Button),
+    /// 軸と傾きの向き(true: +方向)
+    Axis(Axis, bool),
+}
+
+impl Input {
+    /// 名前から入力を得る
+    ///
+    pub fn from_name(name: &str) -> Option<Input> {
+        if let Some(axis) = name.strip_suffix('-') {
+            Axis::from_string(axis).map(|axis| Input::Axis(axis, false))
+        } else if let Some(axis) = name.strip_suffix('+') {
+            Axis::from_string(axis).map(|axis| Input::Axis(axis, true))
+        } else {
+            Button::from_string(name).map(Input::Button)
+        }
+    }
+}
+
+/// 入力とキー値の対応一覧を作る
+///
+///   db_keys: ROM データベースの操作名とキー値
+///   config : 設定ファイル、コマンドラインでの割り当て(入力名 -> "0"〜"F")
+pub fn mapping(
+    db_keys: &[(String, u8)],
+    config: &BTreeMap<String, String>,
+) -> Result<Vec<(Input, u8)>, String> {
+    let mut mapping: Vec<(Input, u8)> = Vec::new();
+    let mut set = |name: &str, key: u8| -> Result<(), String> {
+        let input = Input::from_name(name).ok_or(format!("Unknown gamepad input: '{}'", name))?;
+        mapping.retain(|&(i, _)| i != input);
+        mapping.push((input, key));
+        Ok(())
+    };
+
+    // 既定の割り当て < ROM データベース < 設定
+    for (name, key) in DEFAULT_MAPPING.iter() {
+        set(name, *key)?;
+    }
+    for (db_name, key) in db_keys.iter() {
+        if let Some((_, names)) = DB_INPUTS.iter().find(|(n, _)| n == db_name) {
+            for name in names.iter() {
+                set(name, *key & 0xF)?;
+            }
+        }
+    }
+    for (name, key) in config.iter() {
+        set(name, keymap::parse_key(key)?)?;
+    }
+
+    Ok(mapping)
+}
+
+/// 接続中のゲームパッド
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<GameController>, // 接続中のゲームパッド
+    mapping: Vec<(Input, u8)>, // 入力とキー値の対応
+    deadzone: i16,             // アナログスティックのしきい値
+}
+
+impl Gamepads {
+    /// ゲームパッドの入力を受け付ける
+    ///
+    pub fn new(
+        subsystem: GameControllerSubsystem,
+        mapping: Vec<(Input, u8)>,
+        deadzone: i16,
+    ) -> Gamepads {
+        Gamepads {
+            subsystem,
+            pads: Vec::new(),
+            mapping,
+            deadzone,
+        }
+    }
+
+    /// 割り当てを変える(別の ROM を読み込んだとき。接続中のゲームパッドはそのまま)
+    ///
+    pub fn set_mapping(&mut self, mapping: Vec<(Input, u8)>, deadzone: i16) {
+        self.mapping = mapping;
+        self.deadzone = deadzone;
+    }
+
+    /// 接続/切断のイベントを処理する
+    ///   起動時に接続済みのゲームパッドも ControllerDeviceAdded で通知される
+    ///   接続/切断があれば、その旨のメッセージを返す
+    pub fn handle_event(&mut self, event: &Event) -> Option<String> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let pad = self.subsystem.open(which).ok()?;
+                if self
+                    .pads
+                    .iter()
+                    .all(|p| p.instance_id() != pad.instance_id())
+                {
+                    let message = format!("Gamepad connected: {}", pad.name());
+                    self.pads.push(pad);
+                    return Some(message);
+                }
+                None
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let pos = self.pads.iter().position(|p| p.instance_id() == which)?;
+                let pad = self.pads.remove(pos);
+                Some(format!("Gamepad disconnected: {}", pad.name()))
+            }
+            _ => None,
+        }
+    }
+
+    /// 押されている入力のキー値をキー状態に反映する
+    ///
+    pub fn update(&self, key_status: &mut [u8; 16]) {
+        for pad in self.pads.iter() {
+            for &(input, key) in self.mapping.iter() {
+                let pressed = match input {
+                    Input::Button(button) => pad.button(button),
+                    Input::Axis(axis, true) => pad.axis(axis) > self.deadzone,
+                    Input::Axis(axis, false) => pad.axis(axis) < -self.deadzone,
+                };
+                if pressed {
+                    key_status[(key & 0xF) as usize] = key & 0xF;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//
+// キー割り当て
+//
+//   キーパッドの各キー(0〜F)に、ホスト側のキーを複数割り当てられる。
+//   割り当ては次の順に重ねる。
+//     1. キーボード上の 4x4 の位置(keypad、物理的なキー位置)
+//        またはキーボード配列毎のプリセット(keymap_preset、キートップの文字)
+//     2. キー毎の指定(keymap)。そのキーの割り当てを置き換える
+//   キートップの文字で指定したキーは、現在のキーボード配列で物理的なキーに変換する。
+//
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use sdl2::keyboard::{Keycode, Scancode};
+
+/// プリセット名一覧
+pub const PRESETS: [&str; 6] = ["qwerty", "azerty", "qwertz", "dvorak", "jis", "numpad"];
+
+/// プリセットの 4x4 のキー(キートップの文字)
+///
+pub fn preset(name: &str) -> Option<[&'static str; 16]> {
+    match name {
+        // JIS 配列の英数字部分は QWERTY と同じ
+        "qwerty" | "jis" => Some([
+            "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+        ]),
+        "azerty" => Some([
+            "1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V",
+        ]),
+        "qwertz" => Some([
+            "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V",
+        ]),
+        "dvorak" => Some([
+            "1", "2", "3", "4", "'", ",", ".", "P", "A", "O", "E", "U", ";", "Q", "J", "K",
+        ]),
+        // テンキーの 4x4
+        "numpad" => Some([
+            "Keypad 7",
+            "Keypad 8",
+            "Keypad 9",
+            "Keypad /",
+            "Keypad 4",
+            "Keypad 5",
+            "Keypad 6",
+            "Keypad *",
+            "Keypad 1",
+            "Keypad 2",
+            "Keypad 3",
+            "Keypad -",
+            "Keypad 0",
+            "Keypad .",
+            "Keypad Enter",
+            "Keypad +",
+        ]),
+        _ => None,
+    }
+}
+
+/// キー名から物理的なキーを得る
+///   キートップの文字として現在のキーボード配列で探し、無ければ物理的なキーの名前として探す
+pub fn resolve(name: &str) -> Option<Scancode> {
+    Keycode::from_name(name)
+        .and_then(Scancode::from_keycode)
+        .or_else(|| Scancode::from_name(name))
+}
+
+/// keymap のキー("0"〜"F")をキー値にする
+///
+pub fn parse_key(key: &str) -> Result<u8, String> {
+    u8::from_str_radix(key, 16)
+        .ok()
+        .filter(|&k| k < 16)
+        .ok_or(format!("Invalid keypad key: '{}' (use 0-F)", key))
+}
+
+/// ホスト側のキーとキー値の対応一覧を作る
+///
+///   grid  : キーボード上の 4x4 のキー(物理的なキー位置)
+///   layout: 4x4 の位置に対応するキー値(プラットフォームで決まる)
+///   preset: キーボード配列のプリセット。指定があれば grid の代わりに使う
+///   keymap: キー毎の割り当て
+pub fn bindings(
+    grid: &[Scancode; 16],
+    layout: &[u8; 16],
+    preset: Option<&[&str; 16]>,
+    keymap: &BTreeMap<String, Vec<String>>,
+) -> Result<Vec<(Scancode, u8)>, String> {
+    let unknown = |name: &str| format!("Unknown key name: '{}'", name);
+
+    let mut bindings: Vec<(Scancode, u8)> = match preset {
+        Some(names) => names
+            .iter()
+            .zip(layout.iter())
+            .map(|(name, &key)| Ok((resolve(name).ok_or(unknown(name))?, key)))
+            .collect::<Result<_, String>>()?,
+        None => grid.iter().copied().zip(layout.iter().copied()).collect(),
+    };
+
+    for (key, names) in keymap.iter() {
+        let key = parse_key(key)?;
+        bindings.retain(|&(_, k)| k != key);
+        for name in names.iter() {
+            bindings.push((resolve(name).ok_or(unknown(name))?, key));
+        }
+    }
+
+    Ok(bindings)
+}
+
+/// 2つ目のキーパッド(CHIP-8X)の 4x4 のキー値
+const SECOND_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// 2つ目のキーパッドの既定の位置(テンキー)
+const SECOND_NUMPAD: [Scancode; 16] = [
+    Scancode::Kp7,
+    Scancode::Kp8,
+    Scancode::Kp9,
+    Scancode::KpDivide,
+    Scancode::Kp4,
+    Scancode::Kp5,
+    Scancode::Kp6,
+    Scancode::KpMultiply,
+    Scancode::Kp1,
+    Scancode::Kp2,
+    Scancode::Kp3,
+    Scancode::KpMinus,
+    Scancode::Kp0,
+    Scancode::KpPeriod,
+    Scancode::KpEnter,
+    Scancode::KpPlus,
+];
+
+/// 1つ目のキーパッドがテンキーを使うときの2つ目のキーパッドの位置(1234/QWER/ASDF/ZXCV)
+const SECOND_MAIN: [Scancode; 16] = [
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Q,
+    Scancode::W,
+    Scancode::E,
+    Scancode::R,
+    Scancode::A,
+    Scancode::S,
+    Scancode::D,
+    Scancode::F,
+    Scancode::Z,
+    Scancode::X,
+    Scancode::C,
+    Scancode::V,
+];
+
+/// 2つ目のキーパッド(CHIP-8X)の対応一覧を作る
+///
+///   通常はテンキーに割り当てる。1つ目のキーパッド(first)がテンキーを使っていれば
+///   キーボードの 4x4 の位置に移す。どちらでも1つ目と同じキーは使わない
+///   戻り値: (対応一覧, テンキーから移したか)
+pub fn second_bindings(first: &[(Scancode, u8)]) -> (Vec<(Scancode, u8)>, bool) {
+    let used = |code: &Scancode| first.iter().any(|(c, _)| c == code);
+    let moved = SECOND_NUMPAD.iter().any(used);
+    let grid = if moved { SECOND_MAIN } else { SECOND_NUMPAD };
+    let bindings = grid
+        .into_iter()
+        .zip(SECOND_LAYOUT)
+        .filter(|(code, _)| !used(code))
+        .collect();
+    (bindings, moved)
+}
+
+/// 設定ファイルの [keymap] を書き換える(他の設定やコメントはそのまま残す)
+///
+pub fn save<P: AsRef<Path>>(path: P, keymap: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+    let path = path.as_ref();
+    let err = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+
+    let text = if path.is_file() {
+        fs::read_to_string(path).map_err(|e| err(&e))?
+    } else {
+        String::new()
+    };
+    let mut doc: toml_edit::DocumentMut = text.parse().map_err(|e| err(&e))?;
+
+    let mut table = toml_edit::Table::new();
+    for (key, names) in keymap.iter() {
+        table[key.as_str()] = toml_edit::value(names.iter().collect::<toml_edit::Array>());
+    }
+    doc["keymap"] = toml_edit::Item::Table(table);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| err(&e))?;
+    }
+    fs::write(path, doc.to_string()).map_err(|e| err(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_keypad_avoids_first() {
+        // 1つ目が 4x4 の位置なら、2つ目はテンキー
+        let first: Vec<(Scancode, u8)> = SECOND_MAIN.into_iter().zip(0..16).collect();
+        let (second, moved) = second_bindings(&first);
+        assert!(!moved);
+        assert_eq!(second.len(), 16);
+        assert_eq!(second[0], (Scancode::Kp7, 0x1));
+
+        // 1つ目がテンキー(numpad のプリセット)なら、2つ目は 4x4 の位置に移す
+        let first: Vec<(Scancode, u8)> = SECOND_NUMPAD.into_iter().zip(0..16).collect();
+        let (second, moved) = second_bindings(&first);
+        assert!(moved);
+        assert_eq!(second[0], (Scancode::Num1, 0x1));
+
+        // どちらも使っていれば、重なるキーは2つ目から外す
+        let mut both = first.clone();
+        both.push((Scancode::Q, 0x4));
+        let (second, _) = second_bindings(&both);
+        assert_eq!(second.len(), 15);
+        assert!(second.iter().all(|(code, _)| *code != Scancode::Q));
+    }
+}
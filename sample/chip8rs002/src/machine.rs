@@ -0,0 +1,91 @@
+//
+// 実行エンジン(CHIP-8インタプリタ / COSMAC VIP)共通のインターフェース
+//
+
+use sdl2::pixels::Color;
+
+use crate::chip8::Chip8;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+
+/// サンプル再生の要求
+pub enum SoundEvent {
+    /// 8bit符号無しPCM を再生
+    Play {
+        data: Vec<u8>,
+        rate: u32,
+        looping: bool,
+    },
+    /// 再生を停止
+    Stop,
+}
+
+/// リセットの種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reset {
+    /// CPU(レジスタ、スタック、タイマー)と画面を初期化する。メモリ上の ROM はそのまま
+    Soft,
+    /// メモリも初期化し、ROM イメージを配置し直す
+    ///   起動時に読み込んだ ROM イメージを使い、ファイルは読み直さない
+    ///   (ファイルの変更はファイルの監視で読み込み直す)
+    Hard,
+}
+
+pub trait Machine {
+    /// 実行ループ1回分を実行する
+    ///
+    ///   keys : 押下中のキー値(押されていないキーは 0xFF)
+    ///   keys2: 2つ目のキーパッド(CHIP-8X)
+    fn step(&mut self, keys: &[u8; 16], keys2: &[u8; 16]) -> Result<(), String>;
+
+    /// フレーム境界(60Hz)毎の処理
+    fn tick_frame(&mut self);
+
+    /// 表示内容 (横ピクセル数, 縦ライン数, 画素値)
+    fn frame(&self) -> (usize, usize, &[u8]);
+
+    /// 表示上のサイズ (横, 縦)。画素が正方形でない場合に frame() と異なる
+    fn display_size(&self) -> (usize, usize) {
+        let (width, height, _) = self.frame();
+        (width, height)
+    }
+
+    /// (x, y) の画素の表示色
+    fn pixel_color(&self, _x: usize, _y: usize, pixel: u8) -> Color {
+        if pixel == 1 {
+            Chip8::F_COLOR
+        } else {
+            Chip8::B_COLOR
+        }
+    }
+
+    /// 背景色(画素が消えているときの色)
+    fn background_color(&self) -> Color {
+        self.pixel_color(0, 0, 0)
+    }
+
+    /// リセットする
+    fn reset(&mut self, kind: Reset);
+
+    /// 互換動作を変更する
+    fn set_quirks(&mut self, _quirks: Quirks) {}
+
+    /// 表示色のパレットを変更する
+    fn set_palette(&mut self, _palette: &Palette) {}
+
+    /// サンプル再生の要求があれば取り出す
+    fn take_sound_event(&mut self) -> Option<SoundEvent> {
+        None
+    }
+
+    /// 実行を止めて待っている理由(キー入力待ちなど)
+    fn wait_status(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// レジスタ表示
+    fn report(&self);
+
+    /// エラーの内容(OSD に表示する1行)
+    fn error_mes(&self, mes: &str) -> String;
+}
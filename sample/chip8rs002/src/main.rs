@@ -1,659 +1,1773 @@
 //use std::thread::sleep;
 //use std::time::Duration;
 
-use std::io::{BufReader, Read};
-use std::path::Path;
-use std::{fs, usize};
-
 extern crate sdl2;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use sdl2::audio::AudioDevice;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::keyboard::Mod;
 use sdl2::keyboard::Scancode;
 use sdl2::pixels::Color;
+use sdl2::rect::Rect;
 use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, Sdl};
 
-use rand::prelude::*;
+mod capture;
+mod cdp1802;
+mod chip8;
+mod config;
+mod crt;
+mod detect;
+mod filter;
+mod font;
+mod gamepad;
+mod keymap;
+mod machine;
+mod megachip;
+mod menu;
+mod osd;
+mod palette;
+mod platform;
+mod quirks;
+mod record;
+mod renderer;
+mod romdb;
+mod scheduler;
+mod sound;
+mod touchpad;
+mod upscale;
+mod viewport;
+mod vip;
+mod watch;
+use chip8::Chip8;
+use config::{Config, Settings};
+use crt::CrtEffects;
+use filter::{DisplayFilter, FilterMode};
+use gamepad::Gamepads;
+use machine::{Machine, Reset, SoundEvent};
+use menu::{Action, Menu, Setting};
+use osd::Osd;
+use palette::Palette;
+use platform::Platform;
+use quirks::Quirks;
+use record::Recorder;
+use renderer::Renderer;
+use romdb::RomDb;
+use scheduler::Scheduler;
+use sound::SamplePlayer;
+use touchpad::{Placement, TouchKeypad};
+use upscale::Upscaler;
+use viewport::ScaleMode;
+use vip::Vip;
+use watch::RomWatcher;
 
-#[allow(non_snake_case)]
-struct Chip8 {
-    pub mem: [u8; 0x1000],
+/// CHIP-8 のキーパッドに割り当てるキーボード上の 4x4 のキー
+///
+///   | 1 | 2 | 3 | 4 |
+///   | Q | W | E | R |
+///   | A | S | D | F |
+///   | Z | X | C | V |
+const KEYPAD: [Scancode; 16] = [
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Q,
+    Scancode::W,
+    Scancode::E,
+    Scancode::R,
+    Scancode::A,
+    Scancode::S,
+    Scancode::D,
+    Scancode::F,
+    Scancode::Z,
+    Scancode::X,
+    Scancode::C,
+    Scancode::V,
+];
 
-    pub reg_V: [u8; 16],     // V0 - V15   Register
-    pub reg_I: usize,        // Index Register
-    pub reg_delay_timer: u8, // Delay Timer Register
-    pub reg_sound_timer: u8, // Sound Timer Register
+/// メニューで選べる実行速度(1フレームあたりの命令数、auto は 2ms 毎に1命令)
+const SPEEDS: [&str; 11] = [
+    "auto", "7", "10", "15", "20", "30", "50", "100", "200", "500", "1000",
+];
 
-    pub pc: usize,                      // Program Counter
-    pub stack: [u16; Chip8::STACKSIZE], // 16byte Stack Area
-    pub stack_p: usize,                 // Stack Pointer
+/// ROM データベースの操作名(keys)に割り当てるキーボード上のキー
+const DB_KEYS: [(&str, Scancode); 12] = [
+    ("up", Scancode::Up),
+    ("down", Scancode::Down),
+    ("left", Scancode::Left),
+    ("right", Scancode::Right),
+    ("a", Scancode::Space),
+    ("b", Scancode::Return),
+    ("player2Up", Scancode::I),
+    ("player2Down", Scancode::K),
+    ("player2Left", Scancode::J),
+    ("player2Right", Scancode::L),
+    ("player2A", Scancode::U),
+    ("player2B", Scancode::O),
+];
 
-    pub vram: [[u8; Self::XSIZE]; Self::YSIZE],
-    pub rand255: ThreadRng,
+/// ウインドウの大きさ
+///
+///   横幅は 64x32 のときと同じにして、縦は画面の比率(補正するなら 4:3)に合わせる
+///   画面上のキーパッドを横に並べる場合は、その分だけ横に広げる
+fn window_size(
+    cell_size: usize,
+    display: (usize, usize),
+    aspect: bool,
+    touch_keypad: Option<&TouchKeypad>,
+) -> (u32, u32) {
+    let win_w = (cell_size * Chip8::XSIZE) as u32;
+    let win_h = if aspect {
+        let (ratio_w, ratio_h) = viewport::CORRECTED_ASPECT;
+        win_w * ratio_h / ratio_w
+    } else {
+        win_w * display.1 as u32 / display.0 as u32
+    };
+    touch_keypad
+        .map(|t| t.window_size(win_w, win_h))
+        .unwrap_or((win_w, win_h))
+}
 
-    pub wait_for_key: bool,
+/// Ctrl キーを押しているか
+///
+fn ctrl_pressed(keymod: Mod) -> bool {
+    keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
 }
 
-impl Chip8 {
-    pub const CELLSIZE: usize = 12; // 12 x 12ピクセルサイズ
-                                    //（ピクセルサイズは適当に変更してください）
-    pub const XSIZE: usize = 64; // 横64 ピクセル
-    pub const YSIZE: usize = 32; // 縦32 ライン
-    pub const F_COLOR: sdl2::pixels::Color = Color::RGB(0, 200, 0); // ピクセルの色(Green)
-    pub const B_COLOR: sdl2::pixels::Color = Color::RGB(0, 0, 0); // 背景色(Black)
+/// Shift キーを押しているか
+///
+fn shift_pressed(keymod: Mod) -> bool {
+    keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)
+}
 
-    pub const STACKSIZE: usize = 16; // スタックサイズ 16個
+/// 数値(1以上)の引数を取得
+///
+fn number_arg<T>(option: &str, value: Option<&String>) -> Result<T, String>
+where
+    T: std::str::FromStr + PartialOrd + Default,
+{
+    let value = value.map(|s| s.as_str()).unwrap_or("");
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > T::default())
+        .ok_or(format!("Invalid {}: '{}'", option, value))
+}
 
-    /// 64x32グラフィックを描画
-    ///
-    pub fn draw(&self, canvas: &mut Canvas<Window>) {
-        for yy in 0..Self::YSIZE {
-            for xx in 0..Self::XSIZE {
-                //
-                if self.vram[yy][xx] == 1 {
-                    canvas.set_draw_color(Self::F_COLOR);
-                } else {
-                    canvas.set_draw_color(Self::B_COLOR);
-                }
+/// コマンドラインの指定
+struct Options {
+    cli: Settings,                   // 設定項目
+    config_path: Option<String>,     // 設定ファイル
+    use_config: bool,                // 設定ファイルを読み込む
+    print_config: bool,              // 最終的な設定を表示して終了する
+    rom_db_dir: Option<String>,      // ROM データベースのディレクトリ
+    use_rom_db: bool,                // ROM データベースを使う
+    use_detect: bool,                // ROM の内容からプラットフォームを推定する
+    use_gamepad: bool,               // ゲームパッドを使う
+    machine_name: String,            // 実行エンジン(chip8, vip)
+    vip_monitor: Option<String>,     // COSMAC VIP のモニタROM
+    vip_interpreter: Option<String>, // COSMAC VIP の CHIP-8 インタプリタ
+    screenshot: Option<String>,      // ウインドウを開かずに実行して保存するスクリーンショット
+    frames: u32,                     // ウインドウを開かずに実行するフレーム数
+    native: bool,                    // 実機の解像度で保存する
+    record: Option<String>,          // ウインドウを開かずに実行して保存する動画
+    trace: bool,                     // 命令毎にレジスタを表示する(デバッグ用)
+}
 
-                //
-                let _ = canvas.fill_rect(sdl2::rect::Rect::new(
-                    xx as i32 * Self::CELLSIZE as i32,
-                    yy as i32 * Self::CELLSIZE as i32,
-                    Self::CELLSIZE as u32,
-                    Self::CELLSIZE as u32,
-                ));
-            }
-        }
-    }
+/// ROM の実行の終わり方
+enum Exit {
+    /// 終了する
+    Quit,
+    /// 別の ROM を読み込む (ROM のパス, 実行中に変えた設定)
+    Load(PathBuf, Box<Settings>),
+    /// 同じ ROM を読み込み直す (実行中に変えた設定, 読み込み直す前の設定)
+    Reload(Box<Settings>, Option<Box<Kept>>),
+}
 
-    /// ROMイメージファイルをインメモリ(mem)に読み込む
-    ///
-    pub fn read_rom<P: AsRef<Path>>(&mut self, romimg: P) -> Result<(), std::io::Error> {
-        let fh = fs::File::open(romimg)?;
-        let mut reader = BufReader::new(fh);
-        let mut tmpmem: [u8; 0x1000] = [0u8; 0x1000];
+/// 読み込み直すときに引き継ぐ設定(別の ROM には引き継がない)
+struct Kept {
+    settings: Settings, // 実行速度、パレット、キー割り当て
+    quirks: Quirks,     // 互換動作
+}
 
-        let size = reader.read(&mut tmpmem).unwrap_or(0usize);
+/// ROM を切り替えても作り直さない SDL のウインドウ、入出力
+///   読み込み直してもウインドウの位置、大きさ、フルスクリーンの状態はそのまま
+struct Video {
+    canvas: Canvas<Window>,                           // ウインドウの描画先
+    event_pump: EventPump,                            // イベントポンプ
+    sample_player: Option<AudioDevice<SamplePlayer>>, // サンプル再生(使えない環境では None)
+    gamepads: Option<Gamepads>,                       // ゲームパッド(使わない場合は None)
+    _sdl_context: Sdl,                                // SDL(他より後に解放する)
+}
 
-        //for p in 0..size {
-        //    self.mem[0x200 + p] = tmpmem[p];
-        //}
-        self.mem[0x200..(size + 0x200)].copy_from_slice(&tmpmem[..size]);
+/// 1フレーム(60Hz)あたりの命令数から、1回の待ちあたりの実行命令数を求める
+///
+///   tickrate の指定が無ければ 1命令
+fn steps_for_tickrate(machine_name: &str, tickrate: Option<u32>, wait_ms: i32) -> f64 {
+    match (machine_name, tickrate) {
+        ("chip8", Some(rate)) => rate as f64 * 60.0 * wait_ms as f64 / 1000.0,
+        _ => 1.0,
+    }
+}
 
-        Ok(())
+/// ウインドウを開かずに frames フレーム実行し、フレーム毎の表示内容 (RGB24, 大きさ, 背景色) を on_frame に渡す
+///
+///   timing: (1スライスの時間 [ms], 1フレームの時間 [us])
+///   キーは押されていないものとして実行する
+fn run_headless<F>(
+    machine: &mut dyn Machine,
+    frames: u32,
+    (wait_ms, cycle_us): (i32, i32),
+    steps_per_wait: f64,
+    mut on_frame: F,
+) -> Result<(), String>
+where
+    F: FnMut(&[u8], (usize, usize), Color) -> Result<(), String>,
+{
+    let keys = [0xFF_u8; 16];
+    let mut step_budget = 0.0;
+    let mut rgb = Vec::new();
+    // フレーム境界までのダウンカウンタ [us](端数は次のフレームに持ち越す)
+    let mut time_ct = 0;
+    for _ in 0..frames {
+        time_ct += cycle_us;
+        while time_ct > 0 {
+            step_budget += steps_per_wait;
+            while step_budget >= 1.0 {
+                step_budget -= 1.0;
+                machine
+                    .step(&keys, &keys)
+                    .map_err(|mes| machine.error_mes(&mes))?;
+            }
+            let _ = machine.take_sound_event();
+            time_ct -= wait_ms * 1000;
+        }
+        machine.tick_frame();
+
+        let size = capture::frame_rgb(machine, &mut rgb);
+        on_frame(&rgb, size, machine.background_color())?;
     }
+    Ok(())
+}
 
-    /// レジスタ表示
-    ///
-    pub fn report_reg(&self) {
-        // 汎用レジスタ
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // オプション解析
+    let mut cli = Settings::default(); // コマンドラインで指定した設定
+    let mut show_usage = false;
+    let mut config_path: Option<&String> = None;
+    let mut use_config = true;
+    let mut print_config = false;
+    let mut rom_db_dir: Option<&String> = None;
+    let mut use_rom_db = true;
+    let mut use_detect = true;
+    let mut use_gamepad = true;
+    let mut machine_name = "chip8";
+    let mut vip_monitor: Option<&String> = None;
+    let mut vip_interpreter: Option<&String> = None;
+    let mut chip8_rom: Option<&String> = None;
+    let mut screenshot: Option<&String> = None;
+    let mut record: Option<&String> = None;
+    let mut frames = 60;
+    let mut native = false;
+    let mut trace = false;
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--quirks" => cli.quirks = args_iter.next().cloned().or(Some(String::new())),
+            "--platform" => cli.platform = args_iter.next().cloned().or(Some(String::new())),
+            "--tickrate" => cli.tickrate = Some(number_arg("tickrate", args_iter.next())?),
+            "--keypad" => {
+                // カンマ区切りで16個
+                let keys = args_iter.next().map(|s| s.as_str()).unwrap_or("");
+                cli.keypad = Some(keys.split(',').map(|k| k.trim().to_string()).collect());
+            }
+            "--keymap-preset" => cli.keymap_preset = args_iter.next().cloned(),
+            "--bind" => {
+                // <キー>=<ホストのキー>[,<ホストのキー>...]
+                let bind = args_iter.next().map(|s| s.as_str()).unwrap_or("");
+                let (key, names) = bind.split_once('=').ok_or(format!(
+                    "Invalid binding: '{}' (use <0-F>=<key>[,<key>...])",
+                    bind
+                ))?;
+                let key = format!("{:X}", keymap::parse_key(key.trim())?);
+                cli.keymap.get_or_insert_with(Default::default).insert(
+                    key,
+                    names.split(',').map(|k| k.trim().to_string()).collect(),
+                );
+            }
+            "--gamepad" => {
+                // <入力>=<キー>
+                let bind = args_iter.next().map(|s| s.as_str()).unwrap_or("");
+                let (input, key) = bind.split_once('=').ok_or(format!(
+                    "Invalid gamepad binding: '{}' (use <input>=<0-F>)",
+                    bind
+                ))?;
+                cli.gamepad
+                    .get_or_insert_with(Default::default)
+                    .insert(input.trim().to_string(), key.trim().to_string());
+            }
+            "--gamepad-deadzone" => {
+                cli.gamepad_deadzone = Some(number_arg("gamepad deadzone", args_iter.next())?)
+            }
+            "--no-gamepad" => use_gamepad = false,
+            "--touch-keypad" => cli.touch_keypad = args_iter.next().cloned(),
+            "--scale" => cli.scale = args_iter.next().cloned(),
+            "--aspect-correction" => cli.aspect_correction = Some(true),
+            "--fullscreen" => cli.fullscreen = Some(true),
+            "--osd" => cli.osd = Some(true),
+            "--filter" => cli.filter = args_iter.next().cloned(),
+            "--upscale" => cli.upscale = args_iter.next().cloned(),
+            "--crt" => cli.crt = Some(true),
+            "--crt-effects" => {
+                // カンマ区切り
+                let names = args_iter.next().map(|s| s.as_str()).unwrap_or("");
+                cli.crt_effects = Some(names.split(',').map(|k| k.trim().to_string()).collect());
+            }
+            "--phosphor-half-life" => {
+                cli.phosphor_half_life_ms = Some(number_arg("half-life", args_iter.next())?)
+            }
+            "--blend-frames" => {
+                cli.blend_frames = Some(number_arg("blend frames", args_iter.next())?)
+            }
+            "--palette" => cli.palette = args_iter.next().cloned(),
+            "--fg-color" => cli.fg_color = args_iter.next().cloned(),
+            "--bg-color" => cli.bg_color = args_iter.next().cloned(),
+            "--cell-size" => cli.cell_size = Some(number_arg("cell size", args_iter.next())?),
+            "--wait-ms" => cli.wait_ms = Some(number_arg("wait", args_iter.next())?),
+            "--cycle-ms" => cli.cycle_ms = Some(number_arg("cycle", args_iter.next())?),
+            "--config" => config_path = args_iter.next(),
+            "--no-config" => use_config = false,
+            "--print-config" => print_config = true,
+            "--rom-db" => rom_db_dir = args_iter.next(),
+            "--no-rom-db" => use_rom_db = false,
+            "--no-detect" => use_detect = false,
+            "--machine" => {
+                machine_name = args_iter.next().map(|s| s.as_str()).unwrap_or("");
+            }
+            "--vip-monitor" => vip_monitor = args_iter.next(),
+            "--vip-interpreter" => vip_interpreter = args_iter.next(),
+            "--rom-dir" => cli.rom_dir = args_iter.next().cloned(),
+            "--screenshot-dir" => cli.screenshot_dir = args_iter.next().cloned(),
+            "--screenshot" => screenshot = args_iter.next(),
+            "--frames" => frames = number_arg("frames", args_iter.next())?,
+            "--native" => native = true,
+            "--trace" => trace = true,
+            "--record" => record = args_iter.next(),
+            "--record-format" => cli.record_format = args_iter.next().cloned(),
+            "--no-watch" => cli.watch = Some(false),
+            "--keep-settings" => cli.keep_settings = Some(true),
+            "--fast-forward" => {
+                cli.fast_forward = Some(number_arg("fast forward", args_iter.next())?)
+            }
+            "-h" | "--help" => show_usage = true,
+            _ => chip8_rom = Some(arg),
+        }
+    }
+    if show_usage {
+        println!("  Usage: chip8rs002 [--platform <chip8|chip8x|hires|chip10|megachip8|eti660|dream6800>] [--quirks <default|vip>]");
         println!(
-            "[PC:{:04x}] {:02x} {:02x}",
-            self.pc,
-            self.mem[self.pc],
-            self.mem[self.pc + 1]
+            "                    [--tickrate <n>] [--rom-db <dir> | --no-rom-db] [--no-detect]"
         );
-        for i in 0..16 {
-            print!(" V{:X}:{:02x}", i, self.reg_V[i]);
-            if i == 7 {
-                println!();
+        println!("                    [--keypad <16 keys> | --keymap-preset <qwerty|azerty|qwertz|dvorak|jis|numpad>]");
+        println!("                    [--bind <0-F>=<key>[,<key>...]] [--palette <name>]");
+        println!("                    [--fg-color <#rrggbb>] [--bg-color <#rrggbb>]");
+        println!(
+            "                    [--gamepad <input>=<0-F>] [--gamepad-deadzone <n> | --no-gamepad]"
+        );
+        println!("                    [--touch-keypad <off|beside|over>] [--scale <integer|fit|stretch>]");
+        println!("                    [--aspect-correction] [--fullscreen]");
+        println!("                    [--filter <off|phosphor|blend|deflicker>] [--phosphor-half-life <ms>] [--blend-frames <n>]");
+        println!("                    [--upscale <none|scale2x|scale3x|eagle|xbr>] [--crt]");
+        println!(
+            "                    [--osd] [--crt-effects <scanlines,grid,bloom,vignette,curvature>]"
+        );
+        println!("                    [--cell-size <n>] [--wait-ms <n>] [--cycle-ms <n>] [--fast-forward <n>]");
+        println!("                    [--config <file> | --no-config] [--print-config]");
+        println!("                    [--rom-dir <dir>] [--no-watch] [--keep-settings] [<CHIP-8 ROM Image>]");
+        println!(
+            "                    [--screenshot-dir <dir>] [--record-format <gif|apng>] [--trace]"
+        );
+        println!("         chip8rs002 --screenshot <file.png> [--frames <n>] [--native] [options] <CHIP-8 ROM Image>");
+        println!("         chip8rs002 --record <file.gif|file.png> [--frames <n>] [--native] [options] <CHIP-8 ROM Image>");
+        println!("         chip8rs002 --machine vip --vip-monitor <VIP Monitor ROM>");
+        println!("                    --vip-interpreter <CHIP-8 Interpreter> <CHIP-8 ROM Image>");
+        println!("  Without a ROM image, starts from the ROM browser in the menu (F1)");
+        println!("  A ROM image dropped onto the window is loaded, and the loaded ROM image is reloaded when it changes");
+        println!("  --keep-settings keeps every setting changed while running (quirks, speed, palette, keys, scale ...)");
+        println!("  across reloads; without it a reload starts again from the config file and the command line");
+        return Ok(());
+    }
+
+    let options = Options {
+        cli,
+        config_path: config_path.cloned(),
+        use_config,
+        print_config,
+        rom_db_dir: rom_db_dir.cloned(),
+        use_rom_db,
+        use_detect,
+        use_gamepad,
+        machine_name: machine_name.to_string(),
+        vip_monitor: vip_monitor.cloned(),
+        vip_interpreter: vip_interpreter.cloned(),
+        screenshot: screenshot.cloned(),
+        frames,
+        native,
+        record: record.cloned(),
+        trace,
+    };
+
+    // ROM を切り替える度に実行し直す
+    //   実行中に変えた設定(拡大方法、パレットなど)は次の ROM に引き継ぐ
+    let mut rom = chip8_rom.cloned();
+    let mut runtime = Settings::default();
+    let mut kept = None;
+    let mut video = None;
+    let mut notice = None;
+    loop {
+        let result = run(
+            &options,
+            rom.as_deref(),
+            &runtime,
+            kept.as_deref(),
+            &mut video,
+            notice.take().as_deref(),
+        );
+        // ウインドウを開いた後に ROM が読めなくなった場合(ドロップ、メニュー、読み込み直し)は
+        // 終了せずにエラーを表示して ROM の選択に戻る
+        let exit = match result {
+            Err(e) if video.is_some() && rom.is_some() => {
+                eprintln!("{}", e);
+                notice = Some(e);
+                rom = None;
+                kept = None;
+                continue;
+            }
+            result => result?,
+        };
+        match exit {
+            Exit::Quit => return Ok(()),
+            Exit::Load(path, changed) => {
+                rom = Some(path.to_string_lossy().into_owned());
+                runtime = *changed;
+                kept = None;
+            }
+            Exit::Reload(changed, current) => {
+                println!("Reloading {}", rom.as_deref().unwrap_or_default());
+                runtime = *changed;
+                kept = current;
             }
         }
+    }
+}
 
-        // 特殊レジスタ
-        println!();
-        println!(
-            " I:{:04x}  DT:{:02x}  SP:{:02x}",
-            self.reg_I, self.reg_delay_timer, self.stack_p
-        );
+/// 1つの ROM を実行する
+///
+///   chip8_rom: ROM イメージのパス(無ければメニューの ROM の選択から始める)
+///   runtime  : 実行中に変えた設定(コマンドラインの指定より優先する)
+///   kept     : 読み込み直す前の設定(引き継ぐ場合のみ。他の全ての指定より優先する)
+///   video    : 前の ROM で開いたウインドウ(無ければ開く)
+///   notice   : 開始時に OSD に表示するエラー(前の ROM が読めなかった場合など)
+fn run(
+    options: &Options,
+    chip8_rom: Option<&str>,
+    runtime: &Settings,
+    kept: Option<&Kept>,
+    video: &mut Option<Video>,
+    notice: Option<&str>,
+) -> Result<Exit, String> {
+    let config_path = options.config_path.as_ref();
+    let use_config = options.use_config;
+    let rom_db_dir = options.rom_db_dir.as_ref();
+    let use_rom_db = options.use_rom_db;
+    let use_detect = options.use_detect;
+    let use_gamepad = options.use_gamepad;
+    let machine_name = options.machine_name.as_str();
+    let vip_monitor = options.vip_monitor.as_ref();
+    let vip_interpreter = options.vip_interpreter.as_ref();
+    let runtime = runtime.clone();
 
-        // スタック領域
-        println!(" stack:{:?}", self.stack);
+    // ROM イメージを読み込む(読めなければ呼び出し側でメニューに戻る)
+    let rom_image = match chip8_rom {
+        Some(path) => Some(std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => None,
+    };
+    let rom_sha1 = rom_image.as_ref().map(|rom| RomDb::sha1_hex(rom));
 
-        // メインメモリ
-        //print!("  mem[0b37]:{:?}", self.mem[0x0b37]);
-        println!();
+    // 設定ファイルを読み込み、ROM 毎の設定とコマンドラインの指定を重ねる
+    let config = match (use_config, config_path) {
+        (false, _) => Config::default(),
+        (true, Some(path)) => Config::load(path)?,
+        (true, None) => match Config::default_path().filter(|path| path.is_file()) {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        },
+    };
+    let rom_name = chip8_rom
+        .and_then(|path| Path::new(path).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut settings = config.for_rom(&rom_name, rom_sha1.as_deref());
+    settings.merge(&options.cli);
+    settings.merge(&runtime);
+    if let Some(kept) = kept {
+        settings.merge(&kept.settings);
     }
+    let platform = settings.platform()?;
+    let quirks = settings.quirks()?;
 
-    /// エラー表示
-    ///
-    pub fn error_mes<T: AsRef<str>>(&self, mes: T) {
-        println!("---");
-        println!("--- {}", mes.as_ref());
-        println!("---");
+    // ROM データベースから設定を調べる(コマンドラインや設定ファイルの指定が優先)
+    let rom_db = if use_rom_db {
+        Some(match rom_db_dir {
+            Some(dir) => RomDb::load(dir)?,
+            None => match RomDb::user_dir().filter(|dir| dir.is_dir()) {
+                // 更新版が置いてあればそちらを使う
+                Some(dir) => RomDb::load(&dir).unwrap_or_else(|e| {
+                    println!("ROM database: {} (using bundled database)", e);
+                    RomDb::bundled()
+                }),
+                None => RomDb::bundled(),
+            },
+        })
+    } else {
+        None
+    };
+    let mut rom_settings = rom_db
+        .as_ref()
+        .zip(rom_image.as_ref())
+        .and_then(|(db, rom)| db.lookup(rom))
+        .unwrap_or_default();
+    if let Some(title) = &rom_settings.title {
         println!(
-            "---   [PC:{:04x}] {:02x}{:02x} {:02x}{:02x}",
-            self.pc,
-            self.mem[self.pc],
-            self.mem[self.pc + 1],
-            self.mem[self.pc + 2],
-            self.mem[self.pc + 3]
+            "ROM database: {} [{}]",
+            title,
+            rom_settings.platform_id.as_deref().unwrap_or("-")
+        );
+    }
+    if rom_settings.platform.is_none() {
+        if let Some(id) = &rom_settings.platform_id {
+            println!("ROM database: unsupported platform '{}'", id);
+        }
+    }
+    if !rom_settings.unsupported_quirks.is_empty() {
+        println!(
+            "ROM database: quirks not emulated (ignored): {}",
+            rom_settings.unsupported_quirks.join(", ")
         );
     }
-}
 
-fn main() -> Result<(), String> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("  Usage: chip8rs002 <CHIP-8 ROM Image>");
-        return Ok(());
+    // データベースで分からなければ、ROMイメージの内容から推定する
+    if rom_settings.platform.is_none() && use_detect {
+        if let Some(rom) = &rom_image {
+            let detection = detect::scan(rom);
+            println!(
+                "Detected platform: {:?} {:?}",
+                detection.platform, detection.quirks
+            );
+            for reason in detection.reasons.iter() {
+                println!("  - {}", reason);
+            }
+            rom_settings.platform = Some(detection.platform);
+            rom_settings.quirks = Some(detection.quirks);
+        }
+    }
+    let platform = platform
+        .or(rom_settings.platform)
+        .unwrap_or(Platform::Chip8);
+    let tickrate = settings.tickrate.or(rom_settings.tickrate);
+
+    // 互換動作の指定が無ければ、ROM データベースかプラットフォームの既定値を使う
+    //   (プラットフォームを指定した場合はデータベースの互換動作を使わない)
+    let db_quirks = rom_settings
+        .quirks
+        .filter(|_| rom_settings.platform == Some(platform));
+    let quirks = kept
+        .map(|kept| kept.quirks)
+        .or(quirks)
+        .or(db_quirks)
+        .unwrap_or(platform.default_quirks());
+
+    // 表示色のパレット
+    //   パレットの指定 > ROM データベースの色 > 既定のテーマ。背景色、ピクセルの色の指定はさらに上書きする
+    let user_palettes = settings.palettes.clone().unwrap_or_default();
+    for name in user_palettes.keys() {
+        Palette::find(name, &user_palettes)?;
+    }
+    let mut palette = match (settings.palette()?, rom_settings.colors) {
+        (Some(palette), _) => palette,
+        (None, Some(colors)) => Palette::default().with_colors(colors),
+        (None, None) => Palette::default(),
+    };
+    if let Some(colors) = settings.colors(palette.colors())? {
+        palette = palette.with_colors(colors);
+    }
+    let palette_names = Palette::names(&user_palettes);
+
+    // キーボード上の 4x4 のキー(プリセットの指定があればそちらを使う)
+    let keypad = settings.keypad()?.unwrap_or(KEYPAD);
+    let keymap_preset = settings.keymap_preset()?;
+    let keymap_preset_name = settings
+        .keymap_preset
+        .clone()
+        .unwrap_or("default".to_string());
+    let keymap = settings.keymap.clone().unwrap_or_default();
+    for key in keymap.keys() {
+        keymap::parse_key(key)?;
     }
-    let chip8_rom = &args[1];
 
-    // Chip8構造体を初期化
-    let mut chip8 = Chip8 {
-        mem: [0_u8; 0x1000],
+    // ゲームパッドの割り当て
+    let gamepad_mapping = gamepad::mapping(
+        &rom_settings.keys,
+        settings.gamepad.as_ref().unwrap_or(&Default::default()),
+    )?;
+    let gamepad_deadzone = settings
+        .gamepad_deadzone
+        .map(|n| n.min(i16::MAX as u16) as i16)
+        .unwrap_or(gamepad::DEFAULT_DEADZONE);
 
-        reg_V: [0_u8; 16],
-        reg_I: 0,
-        reg_delay_timer: 0,
-        reg_sound_timer: 0,
+    // 画面上のキーパッド
+    let touch_placement = match &settings.touch_keypad {
+        Some(name) => Placement::from_name(name)?,
+        None => None,
+    };
+
+    // 1ピクセルの大きさ
+    let cell_size = settings.cell_size.unwrap_or(Chip8::CELLSIZE);
+
+    // 拡大方法、縦横比の補正、フルスクリーン
+    let scale_mode = match &settings.scale {
+        Some(name) => ScaleMode::from_name(name).ok_or(format!(
+            "Unknown scale mode: '{}' (available: {})",
+            name,
+            ScaleMode::NAMES.join(", ")
+        ))?,
+        None => ScaleMode::Fit,
+    };
+    let aspect_correction = settings.aspect_correction.unwrap_or(false);
+    let fullscreen = settings.fullscreen.unwrap_or(false);
+
+    // ちらつき対策のフィルタ
+    let filter_mode = match &settings.filter {
+        Some(name) => FilterMode::from_name(name).ok_or(format!(
+            "Unknown filter: '{}' (available: {})",
+            name,
+            FilterMode::NAMES.join(", ")
+        ))?,
+        None => FilterMode::Off,
+    };
+    let half_life_ms = settings
+        .phosphor_half_life_ms
+        .map(|ms| ms as f32)
+        .unwrap_or(filter::DEFAULT_HALF_LIFE_MS);
+    let blend_frames = settings
+        .blend_frames
+        .unwrap_or(filter::DEFAULT_BLEND_FRAMES);
 
-        pc: 0x200, // 実行開始アドレス
-        stack: [0_u16; 16],
-        stack_p: 0,
+    // ドット絵向けの拡大
+    let upscaler = match &settings.upscale {
+        Some(name) => Upscaler::from_name(name).ok_or(format!(
+            "Unknown upscaler: '{}' (available: {})",
+            name,
+            Upscaler::NAMES.join(", ")
+        ))?,
+        None => Upscaler::None,
+    };
+
+    // ブラウン管風の効果
+    let crt_effects = match &settings.crt_effects {
+        Some(names) => CrtEffects::from_names(names, settings.crt.unwrap_or(false))?,
+        None => CrtEffects::from_names(&crt::DEFAULT_EFFECTS, settings.crt.unwrap_or(false))?,
+    };
+
+    // 経過時間
+    let wait_ms = settings.wait_ms.unwrap_or(2) as i32; //  2ms Wait
+    let cycle_us = settings
+        .cycle_ms
+        .map(|ms| ms as i32 * 1000)
+        .unwrap_or(platform.timer_cycle_us()); // 60Hz (50Hz のプラットフォームは 20ms)
+    let cycle = cycle_us as f64 / 1000.0; // [ms]
 
-        // VRAM領域。オール0 で初期化
-        vram: [[0_u8; Chip8::XSIZE]; Chip8::YSIZE],
-        rand255: rand::thread_rng(),
-        wait_for_key: false,
+    // 動画の形式(ホットキーで記録する場合)
+    let record_format = match &settings.record_format {
+        Some(name) => record::Format::from_name(name).ok_or(format!(
+            "Unknown recording format: '{}' (available: {})",
+            name,
+            record::Format::NAMES.join(", ")
+        ))?,
+        None => record::Format::Gif,
     };
+    let fast_forward = settings
+        .fast_forward
+        .unwrap_or(scheduler::DEFAULT_FAST_FORWARD);
 
-    // ROMイメージファイルをメインメモリに読み込む
-    if chip8.read_rom(chip8_rom).is_err() {
-        //if chip8.read_rom("./IBM_Logo.ch8").is_err() {
-        panic!(r#"File Not Found!!"#);
+    // 最終的な設定を表示して終了
+    if options.print_config {
+        let effective = Settings {
+            platform: Some(platform.name().to_string()),
+            quirks: settings.quirks.clone(),
+            tickrate,
+            keypad: Some(keypad.iter().map(|key| key.name().to_string()).collect()),
+            keymap_preset: settings.keymap_preset.clone(),
+            keymap: settings.keymap.clone(),
+            gamepad_deadzone: Some(gamepad_deadzone as u16),
+            gamepad: settings.gamepad.clone(),
+            touch_keypad: settings.touch_keypad.clone(),
+            palette: Some(palette.name.clone()),
+            palettes: settings.palettes.clone(),
+            fg_color: Some(config::format_color(palette.colors[1])),
+            bg_color: Some(config::format_color(palette.colors[0])),
+            cell_size: Some(cell_size),
+            scale: Some(scale_mode.name().to_string()),
+            aspect_correction: Some(aspect_correction),
+            fullscreen: Some(fullscreen),
+            osd: settings.osd.or(Some(false)),
+            filter: Some(filter_mode.name().to_string()),
+            phosphor_half_life_ms: Some(half_life_ms as u32),
+            blend_frames: Some(blend_frames),
+            upscale: Some(upscaler.name().to_string()),
+            crt: Some(crt_effects.enabled),
+            crt_effects: Some(crt_effects.names()),
+            watch: settings.watch.or(Some(true)),
+            keep_settings: settings.keep_settings.or(Some(false)),
+            fast_forward: Some(fast_forward),
+            screenshot_dir: settings.screenshot_dir.clone(),
+            record_format: Some(record_format.name().to_string()),
+            wait_ms: Some(wait_ms as u32),
+            // 既定の 60Hz はミリ秒単位では表せないので、指定があった場合のみ
+            cycle_ms: settings.cycle_ms,
+            rom_dir: settings.rom_dir.clone(),
+        };
+        println!("# quirks: {:?}", quirks);
+        print!("{}", effective.to_toml());
+        return Ok(Exit::Quit);
+    }
+
+    // 実行エンジンを初期化し、ROMイメージファイルをメインメモリに読み込む
+    let mut machine: Box<dyn Machine> = match machine_name {
+        "chip8" => {
+            let mut chip8 = Chip8::new(platform, quirks);
+            if let Some(rom) = &rom_image {
+                chip8.load_rom(rom.clone());
+            }
+            Box::new(chip8)
+        }
+        "vip" => {
+            // COSMAC VIP: モニタROMとオリジナルのCHIP-8インタプリタが必要
+            let (Some(monitor), Some(interpreter)) = (vip_monitor, vip_interpreter) else {
+                return Err("--machine vip requires --vip-monitor and --vip-interpreter".into());
+            };
+            let mut vip = Vip::new(monitor, interpreter, wait_ms as u32)?;
+            if let Some(rom) = &rom_image {
+                vip.load_rom(rom.clone());
+            }
+            Box::new(vip)
+        }
+        _ => {
+            return Err(format!(
+                "Unknown machine: '{}' (available: chip8, vip)",
+                machine_name
+            ))
+        }
+    };
+    machine.set_palette(&palette);
+
+    // ウインドウを開かずに実行して、最後のフレームや動画を保存する
+    if options.screenshot.is_some() || options.record.is_some() {
+        if chip8_rom.is_none() {
+            return Err("--screenshot and --record require a CHIP-8 ROM image".into());
+        }
+        let new_filter =
+            || DisplayFilter::new(filter_mode, half_life_ms, blend_frames, cycle as f32);
+        let display_size = window_size(cell_size, machine.display_size(), aspect_correction, None);
+        let scale = (!options.native).then_some(capture::Scale {
+            size: (display_size.0 as usize, display_size.1 as usize),
+            upscaler,
+            crt: crt_effects,
+        });
+        let mut filter = new_filter();
+        let mut recorder = options
+            .record
+            .as_ref()
+            .map(|path| Recorder::new(path, cycle, scale.as_ref().map(|_| new_filter())))
+            .transpose()?;
+        let mut last = (Vec::new(), (0, 0));
+        let steps = steps_for_tickrate(machine_name, tickrate, wait_ms);
+        run_headless(
+            machine.as_mut(),
+            options.frames,
+            (wait_ms, cycle_us),
+            steps,
+            |rgb, size, background| {
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.push(rgb, size, background);
+                }
+                let mut rgb = rgb.to_vec();
+                if scale.is_some() {
+                    filter.apply(&mut rgb, background);
+                }
+                last = (rgb, size);
+                Ok(())
+            },
+        )?;
+
+        if let Some(path) = &options.screenshot {
+            let (rgb, size) = match &scale {
+                Some(scale) => (scale.apply(&last.0, last.1), scale.size),
+                None => last,
+            };
+            capture::save_png(path, &rgb, size)?;
+            println!("Screenshot saved to {}", path);
+        }
+        if let Some(recorder) = recorder {
+            let (count, path) = (recorder.count(), recorder.path().display().to_string());
+            recorder.finish(scale.as_ref())?;
+            println!("Recorded {} frames to {}", count, path);
+        }
+        return Ok(Exit::Quit);
     }
 
     // ----------------------------------------
-    // SDL2 初期化
+    // SDL2 初期化(最初の ROM のときだけ。以降はウインドウをそのまま使う)
     // ----------------------------------------
-    let sdl_context = sdl2::init()?;
-    let video_system = sdl_context.video()?;
-
-    let window = video_system
-        .window(
-            "chip8", //
-            // ウインドウXサイズ
-            (Chip8::CELLSIZE * Chip8::XSIZE) as u32,
-            // ウインドウYサイズ
-            (Chip8::CELLSIZE * Chip8::YSIZE) as u32,
-        )
-        .position_centered()
-        .opengl()
-        .build()
-        .map_err(|e| e.to_string())?;
+    let display = machine.display_size();
+    let touch_keypad = touch_placement.map(|p| TouchKeypad::new(p, platform.keypad_layout()));
+    let title = rom_settings
+        .title
+        .as_deref()
+        .or(chip8_rom.map(|_| rom_name.as_str()))
+        .unwrap_or("chip8");
+    let video = match video {
+        Some(video) => {
+            video.reuse(title, gamepad_mapping, gamepad_deadzone)?;
+            video
+        }
+        None => video.insert(Video::open(
+            title,
+            window_size(cell_size, display, aspect_correction, touch_keypad.as_ref()),
+            fullscreen,
+            palette.colors[0],
+            use_gamepad.then_some((gamepad_mapping, gamepad_deadzone)),
+        )?),
+    };
 
-    // グラフィック描画のための canvas を取得
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    // 表示内容はテクスチャに転送して描画する
+    let texture_creator = video.canvas.texture_creator();
+    let renderer = Renderer::new(
+        &texture_creator,
+        DisplayFilter::new(filter_mode, half_life_ms, blend_frames, cycle as f32),
+        upscaler,
+        crt_effects,
+    );
 
-    // ウインドウの描画領域を全て黒で塗り潰す
-    canvas.set_draw_color(Chip8::B_COLOR);
-    canvas.clear();
-    canvas.present();
+    // 画面上の表示
+    let mut osd = Osd::new(settings.osd.unwrap_or(false), cycle);
+    if let Some(mes) = notice {
+        osd.error(mes);
+    }
 
-    // イベントポンプ取得
-    let mut event_pomp = sdl_context.event_pump()?;
+    // ホスト側のキーとキー値の対応
+    //   キーボード上の 4x4 の位置とキー値の対応はプラットフォームで決まる
+    let keypad_layout = platform.keypad_layout();
+    let key_bindings = keymap::bindings(&keypad, &keypad_layout, keymap_preset.as_ref(), &keymap)?;
 
-    // ----------------------------------------
-    // ----------------------------------------
+    // キーの割り当てを保存する設定ファイル
+    let config_save_path = match (use_config, config_path) {
+        (false, _) => None,
+        (true, Some(path)) => Some(PathBuf::from(path)),
+        (true, None) => Config::default_path(),
+    };
 
-    // 経過時間
-    const WAIT_MS: i32 = 2; //  2ms Wait
-    const CYCLE: i32 = 18; // 18ms cycle
-    let mut time_ct = CYCLE; // 18ms毎のダウンカウンタ
-
-    // 実行ループ
-    //let mut result: bool;
-    let mut update_pc: bool;
-    'dec_exec_loop: loop {
-        //result = true;
-        update_pc = false;
-
-        // 現在の各レジスタ、スタック内容を表示
-        chip8.report_reg();
-
-        // 現在のキー状態を取得
-        let key_state = event_pomp.keyboard_state();
-
-        // キー押下の判定
-        let all_key_status = [
-            // | 1 | 2 | 3 | C |
-            if key_state.is_scancode_pressed(Scancode::Num1) {
-                1
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::Num2) {
-                2
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::Num3) {
-                3
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::Num4) {
-                0xC
-            } else {
-                0xFF
-            },
-            // | 4 | 5 | 6 | D |
-            if key_state.is_scancode_pressed(Scancode::Q) {
-                4
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::W) {
-                5
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::E) {
-                6
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::R) {
-                0xD
-            } else {
-                0xFF
-            },
-            // | 7 | 8 | 9 | E |
-            if key_state.is_scancode_pressed(Scancode::A) {
-                7
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::S) {
-                8
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::D) {
-                9
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::F) {
-                0xE
-            } else {
-                0xFF
-            },
-            // | A | 0 | B | F |
-            if key_state.is_scancode_pressed(Scancode::Z) {
-                0xA
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::X) {
-                0
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::C) {
-                0xB
-            } else {
-                0xFF
-            },
-            if key_state.is_scancode_pressed(Scancode::V) {
-                0xF
-            } else {
-                0xFF
-            },
-        ];
+    // ROM データベースで操作名に割り当てられたキー値
+    let db_keys: Vec<(Scancode, u8)> = rom_settings
+        .keys
+        .iter()
+        .filter_map(|(name, key)| {
+            DB_KEYS
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|&(_, code)| (code, *key))
+        })
+        .collect();
 
-        // 残りのイベントを処理
-        //for event in event_pomp.poll_event() {
-        while let Some(event) = event_pomp.poll_event() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'dec_exec_loop,
-                _ => {}
-            }
-        }
-
-        // 命令フェッチ
-        //
-        // 2byte,16bit値のうち
-        // -- 12 〜 15bit目の値
-        let d0 = (chip8.mem[chip8.pc] & 0xF0) >> 4;
-        // --  8 〜 11bit目
-        #[allow(unused_parens)]
-        let d1 = (chip8.mem[chip8.pc] & 0x0F);
-        // --  4 〜 7bit目
-        let d2 = (chip8.mem[chip8.pc + 1] & 0xF0) >> 4;
-        // --  0 〜 3bit目(最下位)
-        #[allow(unused_parens)]
-        let d3 = (chip8.mem[chip8.pc + 1] & 0x0F);
-
-        // デコード、実行
-        //
-        match (d0, d1, d2, d3) {
-            (0x0, 0x0, 0xE, 0x0) => {
-                // 00E0 - CLS
-                for yy in 0..Chip8::YSIZE {
-                    for xx in 0..Chip8::XSIZE {
-                        chip8.vram[yy][xx] = 0;
-                    }
-                }
+    // メニュー(ROM を指定していなければ ROM の選択から始める)
+    //   ROM の選択は --rom-dir、ROM のあるディレクトリ、カレントディレクトリの順に探す
+    let rom_dir = settings
+        .rom_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(|| chip8_rom.and_then(|path| Path::new(path).parent().map(Path::to_path_buf)))
+        .filter(|dir| dir.is_dir())
+        .unwrap_or(PathBuf::from("."));
+    let mut menu = Menu::new(rom_dir);
+    if chip8_rom.is_none() {
+        menu.show_browser(rom_db.as_ref());
+    }
+
+    let session = Session {
+        options,
+        chip8_rom,
+        video,
+        renderer,
+        machine,
+        platform,
+        display,
+        // ROM イメージファイルの変更の監視
+        watcher: chip8_rom
+            .filter(|_| settings.watch.unwrap_or(true))
+            .map(RomWatcher::new),
+        keep_settings: settings.keep_settings.unwrap_or(false),
+        runtime,
+        quirks,
+        tickrate,
+        // 1回の待ち(2ms)あたりの実行命令数。
+        //   tickrate(1フレーム 60Hz あたりの命令数)の指定が無ければ 1命令
+        steps_per_wait: steps_for_tickrate(machine_name, tickrate, wait_ms),
+        wait_ms,
+        step_budget: 0.0,
+        halted: false,
+        palette,
+        palette_names,
+        user_palettes,
+        keypad,
+        keypad_layout,
+        keymap_preset,
+        keymap_preset_name,
+        keymap,
+        key_bindings,
+        db_keys,
+        keypad2_moved: false,
+        rebinding: None,
+        config_save_path,
+        touch_keypad,
+        cell_size,
+        scale_mode,
+        aspect_correction,
+        half_life_ms,
+        blend_frames,
+        cycle,
+        // スクリーンショット、動画を保存するディレクトリ
+        screenshot_dir: settings
+            .screenshot_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or(PathBuf::from(".")),
+        rom_name,
+        record_format,
+        recording: None,
+        record_buf: Vec::new(),
+        area: Rect::new(0, 0, 1, 1),
+        // 実行のスケジューラ(2ms 毎のスライスと 16.667ms 毎のフレーム境界)
+        scheduler: Scheduler::new(wait_ms, cycle_us, fast_forward),
+        osd,
+        menu,
+        rom_db,
+    };
+    session.run()
+}
+
+impl Video {
+    /// SDL を初期化してウインドウを開く
+    ///
+    ///   size    : ウインドウの大きさ(実行中に変えられる)
+    ///   gamepad : ゲームパッドの割り当てとしきい値(使わない場合は None)
+    fn open(
+        title: &str,
+        (win_w, win_h): (u32, u32),
+        fullscreen: bool,
+        background: Color,
+        gamepad: Option<(Vec<(gamepad::Input, u8)>, i16)>,
+    ) -> Result<Video, String> {
+        let sdl_context = sdl2::init()?;
+        let video_system = sdl_context.video()?;
+
+        let mut window = video_system
+            .window(title, win_w, win_h)
+            .position_centered()
+            .resizable()
+            .opengl()
+            .build()
+            .map_err(|e| e.to_string())?;
+        if fullscreen {
+            // ボーダーレスのフルスクリーン(デスクトップの解像度のまま)
+            window.set_fullscreen(FullscreenType::Desktop)?;
+        }
+
+        // グラフィック描画のための canvas を取得
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+        // ウインドウの描画領域を全て黒で塗り潰す
+        canvas.set_draw_color(background);
+        canvas.clear();
+        canvas.present();
+
+        // イベントポンプ取得
+        let event_pump = sdl_context.event_pump()?;
+
+        // サンプル再生用のオーディオデバイス(使えない環境では再生しない)
+        let sample_player = sdl_context
+            .audio()
+            .and_then(|audio| SamplePlayer::open(&audio))
+            .ok();
+
+        // ゲームパッド(使えない環境では使わない)
+        let gamepads = gamepad.and_then(|(mapping, deadzone)| {
+            sdl_context
+                .game_controller()
+                .ok()
+                .map(|subsystem| Gamepads::new(subsystem, mapping, deadzone))
+        });
+
+        Ok(Video {
+            canvas,
+            event_pump,
+            sample_player,
+            gamepads,
+            _sdl_context: sdl_context,
+        })
+    }
+
+    /// 次の ROM でウインドウを使い回す
+    ///   前の ROM のサンプル再生を止め、タイトルとゲームパッドの割り当てだけ変える
+    fn reuse(
+        &mut self,
+        title: &str,
+        mapping: Vec<(gamepad::Input, u8)>,
+        deadzone: i16,
+    ) -> Result<(), String> {
+        if let Some(device) = self.sample_player.as_mut() {
+            device.lock().handle(SoundEvent::Stop);
+        }
+        if let Some(gamepads) = self.gamepads.as_mut() {
+            gamepads.set_mapping(mapping, deadzone);
+        }
+        self.canvas
+            .window_mut()
+            .set_title(title)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// 1つの ROM の実行中の状態
+///   実行ループと、イベント、メニュー、ホットキーの処理で変える
+struct Session<'a> {
+    options: &'a Options,        // コマンドラインの指定
+    chip8_rom: Option<&'a str>,  // ROM イメージのパス
+    rom_name: String,            // ROM のファイル名(保存するファイル名に使う)
+    video: &'a mut Video,        // ウインドウ、入出力
+    renderer: Renderer<'a>,      // 表示
+    machine: Box<dyn Machine>,   // 実行エンジン
+    platform: Platform,          // プラットフォーム
+    display: (usize, usize),     // 開始時の表示の大きさ(ウインドウの大きさを決める)
+    watcher: Option<RomWatcher>, // ROM イメージファイルの変更の監視
+    keep_settings: bool,         // 読み込み直すときに実行中に変えた設定を引き継ぐ
+    runtime: Settings,           // 実行中に変えた設定
+    quirks: Quirks,              // 互換動作
+    tickrate: Option<u32>,       // 1フレームあたりの実行命令数
+    steps_per_wait: f64,         // 1回の待ちあたりの実行命令数
+    wait_ms: i32,                // 1回の待ちの時間 [ms]
+    step_budget: f64,            // 実行できる命令数(端数は次の待ちに持ち越す)
+    halted: bool,                // 実行エラーで止まっているか(リセットするまで実行しない)
+    palette: Palette,            // 表示色のパレット
+    palette_names: Vec<String>,  // 選べるパレット名
+    // 設定ファイルで名前を付けたパレット
+    user_palettes: BTreeMap<String, Vec<String>>,
+    keypad: [Scancode; 16],  // キーボード上の 4x4 のキー
+    keypad_layout: [u8; 16], // 4x4 の位置に対応するキー値
+    // キーボード配列のプリセット
+    keymap_preset: Option<[&'static str; 16]>,
+    keymap_preset_name: String, // プリセット名(無ければ "default")
+    keymap: BTreeMap<String, Vec<String>>, // キー毎の割り当て
+    key_bindings: Vec<(Scancode, u8)>, // ホスト側のキーとキー値の対応
+    db_keys: Vec<(Scancode, u8)>, // ROM データベースの操作名に割り当てたキー
+    keypad2_moved: bool,        // 2つ目のキーパッドをテンキーから移しているか
+    rebinding: Option<u8>,      // キーの割り当て直し中なら、次に割り当てるキー値
+    config_save_path: Option<PathBuf>, // キーの割り当てを保存する設定ファイル
+    touch_keypad: Option<TouchKeypad>, // 画面上のキーパッド
+    cell_size: usize,           // 1ピクセルの大きさ
+    scale_mode: ScaleMode,      // 拡大方法
+    aspect_correction: bool,    // 4:3 で表示する
+    half_life_ms: f32,          // 残光の半減期 [ms]
+    blend_frames: usize,        // blend で平均するフレーム数
+    cycle: f64,                 // 1フレームの時間 [ms]
+    screenshot_dir: PathBuf,    // スクリーンショット、動画を保存するディレクトリ
+    record_format: record::Format, // ホットキーで記録する動画の形式
+    // 記録中の動画(表示の大きさで書き出す場合はその拡大)
+    recording: Option<(Recorder, Option<capture::Scale>)>,
+    record_buf: Vec<u8>,   // 記録するフレームの作業バッファ
+    area: Rect,            // 最後に表示した位置
+    scheduler: Scheduler,  // 実行のスケジューラ
+    osd: Osd,              // 画面上の表示
+    menu: Menu,            // メニュー
+    rom_db: Option<RomDb>, // ROM データベース(メニューの ROM の選択で使う)
+}
+
+impl Session<'_> {
+    /// 終わるまで実行する
+    ///
+    fn run(mut self) -> Result<Exit, String> {
+        let exit = loop {
+            if let Some(exit) = self.step_loop()? {
+                break exit;
             }
-            (0x0, 0x0, 0xE, 0xE) => {
-                // 00EE - RET
-                if chip8.stack_p > 0 {
-                    chip8.pc = chip8.stack[chip8.stack_p - 1] as usize;
-                    update_pc = true;
-                    chip8.stack_p -= 1;
-                } else {
-                    // スタックに空である
-                    chip8.error_mes("Stack Empty");
-                    //result = false;
-                    break 'dec_exec_loop;
-                }
+        };
+
+        // 記録中の動画を書き出す
+        if let Some((recorder, scale)) = self.recording.take() {
+            let (count, path) = (recorder.count(), recorder.path().display().to_string());
+            match recorder.finish(scale.as_ref()) {
+                Ok(()) => println!("Recorded {} frames to {}", count, path),
+                Err(e) => println!("Recording not saved: {}", e),
             }
-            (0x0, n2, n1, n0) => {
-                // 0nnn - SYS addr
-                chip8.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
-                update_pc = true;
+        }
+
+        Ok(exit)
+    }
+
+    /// 実行ループ1回分(入力、イベント、実行、表示、待ち)
+    ///
+    ///   終わるなら終わり方を返す
+    fn step_loop(&mut self) -> Result<Option<Exit>, String> {
+        let (keys, keys2, fast_forward_held) = self.read_keys();
+
+        // 残りのイベントを処理
+        while let Some(event) = self.video.event_pump.poll_event() {
+            if let Some(exit) = self.handle_event(event)? {
+                return Ok(Some(exit));
             }
-            (0x1, n2, n1, n0) => {
-                // 1nnn - JP addr
-                chip8.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
-                update_pc = true;
+        }
+
+        if let Some(exit) = self.poll_watcher() {
+            return Ok(Some(exit));
+        }
+
+        self.execute(&keys, &keys2, fast_forward_held);
+        self.present()?;
+
+        // 2ms 待つ(早送り中は待たない)
+        self.scheduler.wait();
+        Ok(None)
+    }
+
+    /// 現在のキー状態から、キーパッドの押下状態を求める
+    ///
+    ///   戻り値: (キーパッド, 2つ目のキーパッド(CHIP-8X), 早送りのキーを押しているか)
+    fn read_keys(&mut self) -> ([u8; 16], [u8; 16], bool) {
+        let key_state = self.video.event_pump.keyboard_state();
+
+        // Tab を押している間は早送り
+        let fast_forward_held = key_state.is_scancode_pressed(Scancode::Tab);
+
+        // Ctrl/Alt/GUI を押している間はホットキーなので、キーボードからはキーパッドに送らない
+        let modifier_held = [
+            Scancode::LCtrl,
+            Scancode::RCtrl,
+            Scancode::LAlt,
+            Scancode::RAlt,
+            Scancode::LGui,
+            Scancode::RGui,
+        ]
+        .into_iter()
+        .any(|code| key_state.is_scancode_pressed(code));
+
+        // キー押下の判定(ROM データベースのキー割り当ても合わせる)
+        let mut keys = [0xFF_u8; 16];
+        for &(code, key) in self.key_bindings.iter().chain(self.db_keys.iter()) {
+            if !modifier_held && key_state.is_scancode_pressed(code) {
+                keys[(key & 0xF) as usize] = key & 0xF;
             }
-            (0x2, n2, n1, n0) => {
-                // 2nnn - CALL addr
-                if chip8.stack_p < (16 - 1) {
-                    chip8.stack[chip8.stack_p] = ((chip8.pc + 2) & 0xFFFF) as u16;
-                    chip8.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
-                    update_pc = true;
+        }
+        if let Some(gamepads) = self.video.gamepads.as_ref() {
+            gamepads.update(&mut keys);
+        }
+        if let Some(touch_keypad) = &self.touch_keypad {
+            touch_keypad.update(&mut keys);
+        }
 
-                    chip8.stack_p += 1;
-                } else {
-                    // スタックが一杯で、空きが無い
-                    chip8.error_mes("Stack Full");
-                    //result = false;
-                    break 'dec_exec_loop;
-                }
+        // 2つ目のキーパッド(CHIP-8X)はテンキーに割り当てる
+        //   1つ目がテンキーを使っていれば(numpad のプリセットなど)、キーボードの 4x4 に移す
+        let (keypad2, moved) = keymap::second_bindings(&self.key_bindings);
+        let mut keys2 = [0xFF_u8; 16];
+        for &(code, key) in keypad2.iter() {
+            if !modifier_held && key_state.is_scancode_pressed(code) {
+                keys2[key as usize] = key;
             }
-            (0x3, x, k1, k0) => {
-                // 3xkk - SE Vx, byte
-                if chip8.reg_V[x as usize] == (k1 << 4) | k0 {
-                    chip8.pc += 4;
-                    update_pc = true;
-                }
+        }
+        if moved != self.keypad2_moved && self.platform == Platform::Chip8X {
+            self.osd.message(if moved {
+                "Keypad 2 moved to 1234/QWER/ASDF/ZXCV (numpad is used by keypad 1)"
+            } else {
+                "Keypad 2 moved back to the numpad"
+            });
+        }
+        self.keypad2_moved = moved;
+
+        (keys, keys2, fast_forward_held)
+    }
+
+    /// イベントを1つ処理する
+    ///
+    ///   終わるなら終わり方を返す
+    fn handle_event(&mut self, event: Event) -> Result<Option<Exit>, String> {
+        // ゲームパッドの接続/切断
+        if let Some(message) = self
+            .video
+            .gamepads
+            .as_mut()
+            .and_then(|g| g.handle_event(&event))
+        {
+            self.osd.message(message);
+        }
+
+        // 画面上のキーパッドのクリック、タッチ
+        if let Some(touch_keypad) = self.touch_keypad.as_mut() {
+            let (win_w, win_h) = self.video.canvas.window().size();
+            touch_keypad.handle_event(&event, win_w, win_h);
+        }
+
+        // ウインドウに落とされたファイルを読み込む
+        if let Event::DropFile { filename, .. } = &event {
+            let path = PathBuf::from(filename);
+            if path.is_file() {
+                return Ok(Some(Exit::Load(path, Box::new(self.runtime.clone()))));
             }
-            (0x4, x, k1, k0) => {
-                // 4xkk - SNE Vx, byte
-                if chip8.reg_V[x as usize] != (k1 << 4 | k0) {
-                    chip8.pc += 4;
-                    update_pc = true;
-                }
+            self.osd.error(format!("Not a file: {}", filename));
+            return Ok(None);
+        }
+
+        // メニューの項目に現在の設定を反映してから、メニューの操作を処理する
+        if !self.menu.open {
+            self.sync_menu();
+        }
+        let menu_was_open = self.menu.open;
+        if let Some(action) = self.menu.handle_event(&event, self.rom_db.as_ref()) {
+            if let Some(exit) = self.menu_action(action)? {
+                return Ok(Some(exit));
             }
-            (0x5, x, y, 0x0) => {
-                // 5xy0 - SE Vx, Vy
-                if chip8.reg_V[x as usize] == chip8.reg_V[y as usize] {
-                    chip8.pc += 4;
-                    update_pc = true;
-                }
+        }
+        if menu_was_open || self.menu.open {
+            return Ok(matches!(event, Event::Quit { .. }).then_some(Exit::Quit));
+        }
+
+        match self.rebinding {
+            Some(key) => self.rebind(event, key),
+            None => self.hotkey(event),
+        }
+    }
+
+    /// メニューの項目に現在の設定を反映する
+    ///
+    fn sync_menu(&mut self) {
+        let quirks_name = Quirks::PROFILES
+            .iter()
+            .find(|name| Quirks::from_profile(name) == Some(self.quirks))
+            .map_or("custom", |name| name);
+        self.menu.set_choice(
+            Setting::Quirks,
+            Quirks::PROFILES.iter().map(|s| s.to_string()).collect(),
+            quirks_name,
+        );
+        self.menu.set_choice(
+            Setting::Speed,
+            SPEEDS.iter().map(|s| s.to_string()).collect(),
+            &self
+                .tickrate
+                .map_or("auto".to_string(), |rate| rate.to_string()),
+        );
+        self.menu.set_choice(
+            Setting::Palette,
+            self.palette_names.clone(),
+            &self.palette.name,
+        );
+        self.menu.set_choice(
+            Setting::Scale,
+            ScaleMode::NAMES.iter().map(|s| s.to_string()).collect(),
+            self.scale_mode.name(),
+        );
+        self.menu.set_choice(
+            Setting::Keymap,
+            std::iter::once("default")
+                .chain(keymap::PRESETS)
+                .map(|s| s.to_string())
+                .collect(),
+            &self.keymap_preset_name,
+        );
+    }
+
+    /// メニューの操作を実行する
+    ///
+    ///   終わるなら終わり方を返す
+    fn menu_action(&mut self, action: Action) -> Result<Option<Exit>, String> {
+        match action {
+            // ROM が無ければ閉じずに ROM の選択に戻る
+            Action::Resume if self.chip8_rom.is_none() => {
+                self.menu.show_browser(self.rom_db.as_ref())
             }
-            (0x6, x, k1, k0) => {
-                // 6xkk - LD Vx, byte
-                chip8.reg_V[x as usize] = (k1 << 4) | k0;
+            Action::Resume => {}
+            Action::Load(path) => {
+                return Ok(Some(Exit::Load(path, Box::new(self.runtime.clone()))));
             }
-            (0x7, x, k1, k0) => {
-                // 7xkk - ADD Vx, byte
-                let sum = chip8.reg_V[x as usize] as u16 + ((k1 as u16) << 4 | (k0 as u16) & 0xFF);
-
-                // Carry Check
-                chip8.reg_V[0xF] = if sum & 0x100 != 0 { 1 } else { 0 };
+            Action::Quit => return Ok(Some(Exit::Quit)),
+            Action::Rebind => self.start_rebinding(),
+            Action::Set(setting, value) => self.apply_setting(setting, value)?,
+        }
+        Ok(None)
+    }
 
-                chip8.reg_V[x as usize] = sum as u8;
+    /// メニューで変えた設定を反映する
+    ///
+    fn apply_setting(&mut self, setting: Setting, value: String) -> Result<(), String> {
+        match setting {
+            Setting::Quirks => {
+                if let Some(q) = Quirks::from_profile(&value) {
+                    self.quirks = q;
+                    self.machine.set_quirks(q);
+                }
             }
-            (0x8, x, y, 0x0) => {
-                // 8xy0 - LD Vx, Vy
-                chip8.reg_V[x as usize] = chip8.reg_V[y as usize];
+            Setting::Speed => {
+                self.tickrate = value.parse().ok();
+                self.steps_per_wait =
+                    steps_for_tickrate(&self.options.machine_name, self.tickrate, self.wait_ms);
             }
-            (0x8, x, y, 0x1) => {
-                // 8xy1 - OR Vx, Vy
-                chip8.reg_V[x as usize] |= chip8.reg_V[y as usize];
+            Setting::Palette => {
+                self.palette = Palette::find(&value, &self.user_palettes)?;
+                self.machine.set_palette(&self.palette);
+                self.runtime.palette = Some(value.clone());
             }
-            (0x8, x, y, 0x2) => {
-                // 8xy2 - AND Vx, Vy
-                chip8.reg_V[x as usize] &= chip8.reg_V[y as usize];
+            Setting::Scale => {
+                self.scale_mode = ScaleMode::from_name(&value).unwrap_or(self.scale_mode);
+                self.runtime.scale = Some(value.clone());
             }
-            (0x8, x, y, 0x3) => {
-                // 8xy3 - XOR Vx, Vy
-                chip8.reg_V[x as usize] ^= chip8.reg_V[y as usize];
+            Setting::Keymap => {
+                self.keymap_preset = keymap::preset(&value);
+                self.keymap_preset_name = value.clone();
+                self.key_bindings = self.bindings()?;
+                // "default" なら実行中のプリセットの指定を消す
+                self.runtime.keymap_preset = self.keymap_preset.is_some().then(|| value.clone());
             }
-            (0x8, x, y, 0x4) => {
-                // 8xy4 - ADD Vx, Vy
-                let sum = chip8.reg_V[x as usize] as u16 + chip8.reg_V[y as usize] as u16;
+        }
+        self.osd.message(format!("{:?}: {}", setting, value));
+        Ok(())
+    }
 
-                // Carry Check
-                chip8.reg_V[0xF] = if sum & 0x100 != 0 { 1 } else { 0 };
+    /// 現在のキー割り当てからホスト側のキーとキー値の対応を作り直す
+    ///
+    fn bindings(&self) -> Result<Vec<(Scancode, u8)>, String> {
+        keymap::bindings(
+            &self.keypad,
+            &self.keypad_layout,
+            self.keymap_preset.as_ref(),
+            &self.keymap,
+        )
+    }
 
-                chip8.reg_V[x as usize] = sum as u8;
-            }
-            (0x8, x, y, 0x5) => {
-                // 8xy5 - SUB Vx, Vy
-                let vx = chip8.reg_V[x as usize];
-                let vy = chip8.reg_V[y as usize];
+    /// キーの割り当て直しを始める
+    ///
+    fn start_rebinding(&mut self) {
+        self.osd.message("Key rebinding (Esc to cancel)");
+        self.osd.message("Press the key for 0x0");
+        self.rebinding = Some(0);
+    }
 
-                chip8.reg_V[0xF] = if vx > vy { 1 } else { 0 };
-                chip8.reg_V[x as usize] = vx.wrapping_sub(vy);
+    /// キーの割り当て直し中のイベント
+    ///
+    ///   key: 次に割り当てるキー値
+    fn rebind(&mut self, event: Event, key: u8) -> Result<Option<Exit>, String> {
+        match event {
+            Event::Quit { .. } => return Ok(Some(Exit::Quit)),
+            // 割り当て直しの中止
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => {
+                self.osd.message("Key rebinding cancelled");
+                self.rebinding = None;
+                self.key_bindings = self.bindings()?;
             }
-            (0x8, x, y, 0x6) => {
-                // 8xy6 - SHR Vx {, Vy}
-                let vx = chip8.reg_V[x as usize];
-                let _ = chip8.reg_V[y as usize];
-
-                // LSB check
-                chip8.reg_V[0xF] = if vx & 0x01 != 0x00 { 1 } else { 0 };
-
-                chip8.reg_V[x as usize] >>= 1;
+            // 押されたキーを割り当てる
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat: false,
+                ..
+            } => {
+                self.keymap
+                    .insert(format!("{:X}", key), vec![keycode.name()]);
+                if key < 0xF {
+                    self.rebinding = Some(key + 1);
+                    self.osd
+                        .message(format!("Press the key for 0x{:X}", key + 1));
+                } else {
+                    // 全て割り当てたら設定ファイルに書き込む
+                    self.rebinding = None;
+                    self.key_bindings = self.bindings()?;
+                    match &self.config_save_path {
+                        Some(path) => match keymap::save(path, &self.keymap) {
+                            Ok(()) => self
+                                .osd
+                                .message(format!("Key bindings saved to {}", path.display())),
+                            Err(e) => self.osd.error(format!("Key bindings not saved: {}", e)),
+                        },
+                        None => self.osd.error("Key bindings not saved (--no-config)"),
+                    }
+                }
             }
-            (0x8, x, y, 0x7) => {
-                // 8xy7 - SUBN Vx, Vy
-                let vx = chip8.reg_V[x as usize];
-                let vy = chip8.reg_V[y as usize];
+            _ => {}
+        }
+        Ok(None)
+    }
 
-                if vy > vx {
-                    chip8.reg_V[0xF] = 1;
-                    chip8.reg_V[x as usize] = vy.wrapping_sub(vx);
-                } else {
-                    chip8.reg_V[0xF] = 0;
+    /// ホットキー
+    ///
+    ///   終わるなら終わり方を返す
+    fn hotkey(&mut self, event: Event) -> Result<Option<Exit>, String> {
+        let (code, keymod, repeat) = match event {
+            Event::Quit { .. } => return Ok(Some(Exit::Quit)),
+            Event::KeyDown {
+                keycode: Some(code),
+                keymod,
+                repeat,
+                ..
+            } => (code, keymod, repeat),
+            _ => return Ok(None),
+        };
+        let ctrl = ctrl_pressed(keymod);
+        let shift = shift_pressed(keymod);
+
+        match code {
+            // 終了
+            Keycode::Escape => return Ok(Some(Exit::Quit)),
+            // Pause か Ctrl+P で一時停止の切り替え
+            Keycode::Pause if !repeat => self.scheduler.toggle_pause(),
+            Keycode::P if ctrl && !repeat => self.scheduler.toggle_pause(),
+            // Ctrl+F でコマ送り(実行中なら一時停止する)
+            Keycode::F if ctrl => self.scheduler.advance_frame(),
+            // Ctrl+- / Ctrl+= でスローの実行速度を変える、Ctrl+0 で等速に戻す
+            Keycode::Minus | Keycode::Equals | Keycode::Num0 if ctrl && !repeat => {
+                let speed = match code {
+                    Keycode::Minus => self.scheduler.slower(),
+                    Keycode::Equals => self.scheduler.faster(),
+                    _ => {
+                        self.scheduler.reset_speed();
+                        1.0
+                    }
                 };
+                self.osd.message(format!("Speed: x{}", speed));
             }
-            (0x8, x, y, 0xE) => {
-                // 8xyE - SHL Vx {, Vy}
-                let vx = chip8.reg_V[x as usize] as u16;
-                let _ = chip8.reg_V[y as usize] as u16;
-
-                chip8.reg_V[0xF] = if (vx & 0x80) != 0 { 1 } else { 0 };
-                chip8.reg_V[x as usize] <<= 1;
+            // Ctrl+R でソフトリセット、Ctrl+Shift+R でハードリセット
+            //   ハードリセットはメモリ上の ROM イメージから戻す(ファイルは読み直さない)
+            Keycode::R if ctrl && !repeat => {
+                let kind = if shift { Reset::Hard } else { Reset::Soft };
+                self.reset(kind);
+                self.osd.message(format!("{:?} reset", kind));
             }
-            (0x9, x, y, 0x0) => {
-                // 9xy0 - SNE Vx, Vy
-                if chip8.reg_V[x as usize] != chip8.reg_V[y as usize] {
-                    chip8.pc += 4;
-                    update_pc = true;
-                }
+            // Ctrl+K で次の互換動作のプロファイルに切り替えてハードリセット
+            Keycode::K if ctrl && !repeat => {
+                let pos = Quirks::PROFILES
+                    .iter()
+                    .position(|name| Quirks::from_profile(name) == Some(self.quirks))
+                    .map_or(0, |pos| (pos + 1) % Quirks::PROFILES.len());
+                self.quirks = Quirks::from_profile(Quirks::PROFILES[pos]).unwrap_or(self.quirks);
+                self.machine.set_quirks(self.quirks);
+                self.reset(Reset::Hard);
+                self.osd
+                    .message(format!("Hard reset with quirks: {}", Quirks::PROFILES[pos]));
             }
-            (0xA, n2, n1, n0) => {
-                // Annn - LD I, addr
-                chip8.reg_I = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize;
-            }
-            (0xB, n2, n1, n0) => {
-                // Bnnn - JP V0, addr
-                chip8.pc = ((n2 as u16) << 8 | (n1 as u16) << 4 | (n0 as u16)) as usize
-                    + chip8.reg_V[0] as usize;
-                update_pc = true;
-            }
-            (0xC, x, k1, k0) => {
-                // Cxkk - RND Vx, byte
-                let kk = (k1 << 4) | k0;
-                chip8.reg_V[x as usize] = chip8.rand255.gen::<u8>() & kk;
-            }
-            (0xD, x, y, n) => {
-                // Dxyn - DRW Vx, Vy, nibble
-                // アドレスIのｎバイトのスプライトを読み出し(VX,VY)位置に描画する
-                let xx = chip8.reg_V[x as usize] as usize;
-                let yy = chip8.reg_V[y as usize] as usize;
-                let addr = chip8.reg_I;
-
-                // Update VRAM
-                chip8.reg_V[0xf] = 0;
-                for byte in 0..(n as usize) {
-                    let val = chip8.mem[addr + byte];
-                    for bit in 0..8_usize {
-                        let sprite_pixel = (val >> (7 - bit)) & 0x1;
-                        let mut vram_pixel =
-                            chip8.vram[(yy + byte) % Chip8::YSIZE][(xx + bit) % Chip8::XSIZE];
-
-                        chip8.reg_V[0xF] |= sprite_pixel & vram_pixel;
-                        vram_pixel ^= sprite_pixel;
-
-                        chip8.vram[(yy + byte) % Chip8::YSIZE][(xx + bit) % Chip8::XSIZE] =
-                            vram_pixel;
-                    }
-                }
+            // PrintScreen で表示の大きさの、Shift+PrintScreen で実機の解像度のスクリーンショット
+            Keycode::PrintScreen if !ctrl && !repeat => self.screenshot(!shift),
+            // Ctrl+PrintScreen で表示の大きさの、Ctrl+Shift+PrintScreen で実機の解像度の動画の記録を始める/終える
+            Keycode::PrintScreen if !repeat => self.toggle_recording(!shift),
+            // F12 で統計(FPS など)の表示の切り替え
+            Keycode::F12 => {
+                self.osd.show_stats = !self.osd.show_stats;
+                self.runtime.osd = Some(self.osd.show_stats);
             }
-            (0xE, x, 0x9, 0xE) => {
-                // Ex9E - SKP Vx
-                // "キーが押されているか"チェック
-                'keyloop: for key in all_key_status.iter() {
-                    if chip8.reg_V[x as usize] == *key {
-                        chip8.pc += 4;
-                        update_pc = true;
-                        break 'keyloop;
-                    }
-                }
+            // F11 でフルスクリーンの切り替え
+            Keycode::F11 => {
+                let window = self.video.canvas.window_mut();
+                let mode = match window.fullscreen_state() {
+                    FullscreenType::Off => FullscreenType::Desktop,
+                    _ => FullscreenType::Off,
+                };
+                window.set_fullscreen(mode)?;
+                self.runtime.fullscreen = Some(mode != FullscreenType::Off);
             }
-            (0xE, x, 0xA, 0x1) => {
-                // ExA1 - SKNP Vx
-                // "キーが押されていないか"チェック
-                let mut not_det_flg = true;
-                'keyloop: for key in all_key_status.iter() {
-                    if chip8.reg_V[x as usize] == *key {
-                        not_det_flg = false;
-                        break 'keyloop;
-                    }
-                }
-
-                if not_det_flg {
-                    chip8.pc += 4;
-                    update_pc = true;
-                }
+            // F8 で拡大方法の切り替え
+            Keycode::F8 => {
+                self.scale_mode = self.scale_mode.next();
+                self.runtime.scale = Some(self.scale_mode.name().to_string());
+                self.osd
+                    .message(format!("Scale: {}", self.scale_mode.name()));
             }
-            (0xF, x, 0x0, 0x7) => {
-                // Fx07 - LD Vx, D
-                chip8.reg_V[x as usize] = chip8.reg_delay_timer;
+            // F3 でパレットの切り替え
+            Keycode::F3 => {
+                let pos = self
+                    .palette_names
+                    .iter()
+                    .position(|name| *name == self.palette.name)
+                    .map_or(0, |pos| (pos + 1) % self.palette_names.len());
+                self.palette = Palette::find(&self.palette_names[pos], &self.user_palettes)?;
+                self.machine.set_palette(&self.palette);
+                self.runtime.palette = Some(self.palette.name.clone());
+                self.osd.message(format!("Palette: {}", self.palette.name));
             }
-            (0xF, x, 0x0, 0xA) => {
-                // Fx0A - LD Vx, K
-                // キーが入力されるまで全ての実行をストップする。キーが押されるとその値をVxにセットする。
-                chip8.wait_for_key = true;
-
-                'keyloop: for key in all_key_status.iter() {
-                    if *key != 0xFF {
-                        chip8.reg_V[x as usize] = *key;
-                        chip8.wait_for_key = false;
-                        break 'keyloop;
-                    }
-                }
+            // F4 でドット絵向けの拡大の切り替え
+            Keycode::F4 => {
+                self.renderer.upscaler = self.renderer.upscaler.next();
+                self.runtime.upscale = Some(self.renderer.upscaler.name().to_string());
+                self.osd
+                    .message(format!("Upscaler: {}", self.renderer.upscaler.name()));
             }
-            (0xF, x, 0x1, 0x5) => {
-                // Fx15 - LD DT, Vx
-                chip8.reg_delay_timer = chip8.reg_V[x as usize];
-            }
-            (0xF, x, 0x1, 0x8) => {
-                // Fx18 - LD ST, Vx
-                chip8.reg_sound_timer = chip8.reg_V[x as usize];
-            }
-            (0xF, x, 0x1, 0xE) => {
-                // Fx1E - ADD I, Vx
-                chip8.reg_I += (chip8.reg_V[x as usize]) as usize;
-            }
-            (0xF, x, 0x2, 0x09) => {
-                chip8.reg_I = chip8.reg_V[x as usize] as usize * 5;
-            }
-            (0xF, x, 0x3, 0x3) => {
-                // Fx33 - LD B, Vx
-                let addr = chip8.reg_I;
-                let val = chip8.reg_V[x as usize];
-                // 10進表記の百の位、十の位、一の位の値を取る
-                chip8.mem[addr] = val / 100;
-                chip8.mem[addr + 1] = (val % 100) / 10;
-                chip8.mem[addr + 2] = val % 10;
-            }
-            (0xF, x, 0x5, 0x5) => {
-                // Fx55 - LD [I], Vx
-                for i in 0..(x + 1) as usize {
-                    if i < 16 {
-                        chip8.mem[chip8.reg_I + i] = chip8.reg_V[i];
-                    } else {
-                        break;
-                    }
-                }
+            // F5 でブラウン管風の効果の切り替え
+            Keycode::F5 => {
+                let mut crt = self.renderer.crt();
+                crt.enabled = !crt.enabled;
+                self.renderer.set_crt(crt);
+                self.runtime.crt = Some(crt.enabled);
+                self.osd.message(format!("CRT effects: {}", crt.enabled));
             }
-            (0xF, x, 0x6, 0x5) => {
-                // Fx65 - LD Vx, [I]
-                for i in 0..(x + 1) as usize {
-                    if i < 16 {
-                        chip8.reg_V[i] = chip8.mem[chip8.reg_I];
-                        chip8.reg_I += 1;
-                    } else {
-                        break;
-                    }
-                }
+            // F6 でちらつき対策のフィルタの切り替え
+            Keycode::F6 => {
+                let mode = self.renderer.filter.mode().next();
+                self.renderer.filter.set_mode(mode);
+                self.runtime.filter = Some(mode.name().to_string());
+                self.osd.message(format!("Filter: {}", mode.name()));
             }
-            _ => {
-                // 命令コードが無かったら、直ちに終了
-                chip8.error_mes("Not Support Instruction");
-                panic!();
+            // F7 で縦横比の補正の切り替え
+            Keycode::F7 => {
+                self.aspect_correction = !self.aspect_correction;
+                self.runtime.aspect_correction = Some(self.aspect_correction);
+                self.osd
+                    .message(format!("Aspect correction: {}", self.aspect_correction));
             }
+            // F9/F10 でウインドウの大きさ(1ピクセルの大きさ)を変える
+            Keycode::F9 => self.resize(self.cell_size.saturating_sub(1).max(1))?,
+            Keycode::F10 => self.resize(self.cell_size + 1)?,
+            // F2 でキーの割り当て直しを始める
+            Keycode::F2 => self.start_rebinding(),
+            _ => {}
         }
+        Ok(None)
+    }
+
+    /// リセットして、実行エラーの停止とサンプル再生を解く
+    ///
+    fn reset(&mut self, kind: Reset) {
+        self.machine.reset(kind);
+        self.step_budget = 0.0;
+        self.halted = false;
+        if let Some(device) = self.video.sample_player.as_mut() {
+            device.lock().handle(SoundEvent::Stop);
+        }
+    }
 
-        // プログラムカウンタを+2進める(2byte、16bit分)
-        // 下記の場合はプログラムカウンタPCを更新しない
-        //   1)wait_for_key: true キー入力待ちで、実行を一時停止中のため
-        //   2)update_pc   : true 既に各分岐命令でPCを更新済みなので、ここでは更新しない
-        if !chip8.wait_for_key && !update_pc {
-            chip8.pc += 2;
+    /// スクリーンショットを保存する
+    ///
+    ///   scaled: 表示の大きさで保存する(false なら実機の解像度)
+    fn screenshot(&mut self, scaled: bool) {
+        let (rgb, size) = if scaled {
+            let size = (self.area.width() as usize, self.area.height() as usize);
+            (self.renderer.image(size), size)
         } else {
-            println!(" --> wait for any key");
+            let mut rgb = Vec::new();
+            let size = capture::frame_rgb(self.machine.as_ref(), &mut rgb);
+            (rgb, size)
+        };
+        let path = capture::timestamped_path(&self.screenshot_dir, &self.rom_name, "png");
+        match capture::save_png(&path, &rgb, size) {
+            Ok(()) => self
+                .osd
+                .message(format!("Screenshot saved to {}", path.display())),
+            Err(e) => self.osd.error(format!("Screenshot not saved: {}", e)),
         }
+    }
 
-        // 64x32グラフィックを表示
-        chip8.draw(&mut canvas);
-        canvas.present();
+    /// 動画の記録を始める/終える
+    ///
+    ///   scaled: 表示の大きさで記録する(false なら実機の解像度)
+    fn toggle_recording(&mut self, scaled: bool) {
+        if let Some((recorder, scale)) = self.recording.take() {
+            self.osd.recording = false;
+            let (count, path) = (recorder.count(), recorder.path().display().to_string());
+            match recorder.finish(scale.as_ref()) {
+                Ok(()) => self
+                    .osd
+                    .message(format!("Recorded {} frames to {}", count, path)),
+                Err(e) => self.osd.error(format!("Recording not saved: {}", e)),
+            }
+            return;
+        }
+
+        let path = capture::timestamped_path(
+            &self.screenshot_dir,
+            &self.rom_name,
+            self.record_format.extension(),
+        );
+        let scale = scaled.then(|| capture::Scale {
+            size: (self.area.width() as usize, self.area.height() as usize),
+            upscaler: self.renderer.upscaler,
+            crt: self.renderer.crt(),
+        });
+        let filter = scale.as_ref().map(|_| {
+            DisplayFilter::new(
+                self.renderer.filter.mode(),
+                self.half_life_ms,
+                self.blend_frames,
+                self.cycle as f32,
+            )
+        });
+        match Recorder::new(&path, self.cycle, filter) {
+            Ok(recorder) => {
+                self.osd.recording = true;
+                self.osd.message(format!("Recording to {}", path.display()));
+                self.recording = Some((recorder, scale));
+            }
+            Err(e) => self.osd.error(e),
+        }
+    }
+
+    /// 1ピクセルの大きさを変えて、ウインドウの大きさを合わせる(フルスクリーン中はそのまま)
+    ///
+    fn resize(&mut self, cell_size: usize) -> Result<(), String> {
+        self.cell_size = cell_size;
+        let window = self.video.canvas.window_mut();
+        if window.fullscreen_state() == FullscreenType::Off {
+            let (w, h) = window_size(
+                cell_size,
+                self.display,
+                self.aspect_correction,
+                self.touch_keypad.as_ref(),
+            );
+            window.set_size(w, h).map_err(|e| e.to_string())?;
+        }
+        self.runtime.cell_size = Some(cell_size);
+        self.osd.message(format!("Cell size: {}", cell_size));
+        Ok(())
+    }
+
+    /// ROM イメージファイルが変更されたら読み込み直す
+    ///
+    ///   引き継ぐ指定があれば、実行中に変えた設定(拡大方法、フィルタなど)に加えて
+    ///   互換動作、実行速度、パレット、キー割り当ても引き継ぐ
+    ///   無ければ設定ファイルとコマンドラインの指定だけでやり直す
+    fn poll_watcher(&mut self) -> Option<Exit> {
+        if !self.watcher.as_mut().is_some_and(|w| w.poll()) {
+            return None;
+        }
+        if !self.keep_settings {
+            return Some(Exit::Reload(Box::default(), None));
+        }
 
-        // 2ms 待つ
-        ::std::thread::sleep(::std::time::Duration::from_millis(WAIT_MS as u64));
-        //::std::thread::sleep(::std::time::Duration::from_micros(1000_u64));
+        let keymap_preset = self
+            .keymap_preset
+            .is_some()
+            .then(|| self.keymap_preset_name.clone());
+        let kept = Kept {
+            settings: Settings {
+                tickrate: self.tickrate,
+                palette: Some(self.palette.name.clone()),
+                fg_color: Some(config::format_color(self.palette.colors[1])),
+                bg_color: Some(config::format_color(self.palette.colors[0])),
+                keymap_preset,
+                keymap: Some(self.keymap.clone()),
+                ..Default::default()
+            },
+            quirks: self.quirks,
+        };
+        Some(Exit::Reload(
+            Box::new(self.runtime.clone()),
+            Some(Box::new(kept)),
+        ))
+    }
+
+    /// 実時間に合わせて、スライス(2ms)毎に命令フェッチ、デコード、実行
+    ///
+    ///   keys, keys2: キーパッドの押下状態、fast: 早送りのキーを押しているか
+    fn execute(&mut self, keys: &[u8; 16], keys2: &[u8; 16], fast: bool) {
+        // 割り当て直しの間は実行を止める(表示は続ける)
+        //   メニューを開いている間、ROM が無いときも止める。一時停止中はコマ送りだけ実行する
+        //   実行エラーの後は、リセットするまで止める
+        let running =
+            self.rebinding.is_none() && !self.menu.open && self.chip8_rom.is_some() && !self.halted;
+        self.scheduler.update(running, fast);
+        self.osd.paused = self.scheduler.paused();
+        self.osd.rate = self.scheduler.rate();
+
+        'slice_loop: while let Some(frame_end) = self.scheduler.next_slice() {
+            self.step_budget += self.steps_per_wait;
+            while self.step_budget >= 1.0 {
+                self.step_budget -= 1.0;
 
-        // 各タイマーレジスタをカウントダウン
-        time_ct -= WAIT_MS;
-        if time_ct <= 0 {
-            // ディレイタイマーレジスタの更新
-            if chip8.reg_delay_timer > 0 {
-                chip8.reg_delay_timer -= 1;
+                // 現在の各レジスタ、スタック内容を表示(--trace 指定時のみ)
+                if self.options.trace {
+                    self.machine.report();
+                }
+
+                if let Err(mes) = self.machine.step(keys, keys2) {
+                    self.osd.error(self.machine.error_mes(&mes));
+                    self.halted = true;
+                    break 'slice_loop;
+                }
+                self.osd.count_steps(1);
             }
-            // サウンドタイマーレジスタの更新
-            if chip8.reg_sound_timer > 0 {
-                chip8.reg_sound_timer -= 1;
+
+            // サンプル再生の要求を処理
+            if let Some(event) = self.machine.take_sound_event() {
+                if let Some(device) = self.video.sample_player.as_mut() {
+                    device.lock().handle(event);
+                }
             }
 
-            // 初期値に戻す
-            time_ct = CYCLE;
+            // 各タイマーレジスタをカウントダウン
+            //   記録中なら、フレーム毎の表示内容を記録する
+            if frame_end {
+                self.machine.tick_frame();
+                self.osd.count_tick();
+                if let Some((recorder, _)) = self.recording.as_mut() {
+                    let size = capture::frame_rgb(self.machine.as_ref(), &mut self.record_buf);
+                    recorder.push(&self.record_buf, size, self.machine.background_color());
+                }
+            }
         }
     }
 
-    Ok(())
+    /// グラフィックを表示(実時間の1フレームに1回、早送り中は間引かれる)
+    ///
+    ///   ウインドウの大きさと拡大方法から表示位置を決め、余白は黒で埋める
+    fn present(&mut self) -> Result<(), String> {
+        if !self.scheduler.should_present() {
+            return Ok(());
+        }
+        let canvas = &mut self.video.canvas;
+        let (win_w, win_h) = canvas.output_size().unwrap_or((0, 0));
+        let window_area = match &self.touch_keypad {
+            Some(touch_keypad) => touch_keypad.display_rect(win_w, win_h),
+            None => Rect::new(0, 0, win_w.max(1), win_h.max(1)),
+        };
+        //   MegaChip モードの切り替えで大きさが変わるので毎回調べる
+        self.area = viewport::display_rect(
+            self.scale_mode,
+            self.machine.display_size(),
+            self.aspect_correction,
+            window_area,
+        );
+        canvas.set_draw_color(Chip8::B_COLOR);
+        canvas.clear();
+        self.renderer.update(self.machine.as_ref())?;
+        self.renderer.draw(canvas, self.area)?;
+        if let Some(touch_keypad) = &self.touch_keypad {
+            touch_keypad.draw(canvas);
+        }
+        self.menu.draw(canvas);
+        self.osd.count_frame();
+        self.osd.status = self.machine.wait_status();
+        self.osd.draw(canvas);
+        canvas.present();
+        Ok(())
+    }
 }
@@ -0,0 +1,154 @@
+//
+// MegaChip8 拡張
+//
+//   0011 で MegaChip モード(256x192、256色パレット)に切り替わる。
+//   スプライトは1バイト1ピクセルのパレット番号で描画し(0は透明)、
+//   描画は裏画面に対して行い、00E0 で表画面へ反映(表示を更新)する。
+//
+
+use sdl2::pixels::Color;
+
+pub struct MegaChip {
+    pub enabled: bool, // MegaChip モード中か(0011 で ON、0010 で OFF)
+
+    pub palette: [u32; 256], // パレット(ARGB)。0番は透明
+    pub sprite_w: usize,     // スプライトの横幅(03nn)
+    pub sprite_h: usize,     // スプライトの高さ(04nn)
+    pub alpha: u8,           // 画面全体の不透明度(05nn)
+    pub blend: u8,           // スプライトの合成方法(080n)
+    pub collision: u8,       // 衝突判定に使うパレット番号(09nn)
+
+    pub back: Vec<u32>,     // 裏画面(RGB)
+    pub back_idx: Vec<u8>,  // 裏画面(パレット番号、衝突判定用)
+    pub front: Vec<u32>,    // 表画面(RGB)
+    pub front_idx: Vec<u8>, // 表画面(パレット番号)
+}
+
+impl MegaChip {
+    pub const XSIZE: usize = 256; // 横256 ピクセル
+    pub const YSIZE: usize = 192; // 縦192 ライン
+
+    // スプライトの合成方法(080n)
+    pub const BLEND_NORMAL: u8 = 0;
+    pub const BLEND_25: u8 = 1;
+    pub const BLEND_50: u8 = 2;
+    pub const BLEND_75: u8 = 3;
+    pub const BLEND_ADD: u8 = 4;
+    pub const BLEND_MUL: u8 = 5;
+
+    pub fn new() -> MegaChip {
+        MegaChip {
+            enabled: false,
+            palette: [0_u32; 256],
+            sprite_w: 0,
+            sprite_h: 0,
+            alpha: 0xFF,
+            blend: Self::BLEND_NORMAL,
+            collision: 0,
+            back: vec![0_u32; Self::XSIZE * Self::YSIZE],
+            back_idx: vec![0_u8; Self::XSIZE * Self::YSIZE],
+            front: vec![0_u32; Self::XSIZE * Self::YSIZE],
+            front_idx: vec![0_u8; Self::XSIZE * Self::YSIZE],
+        }
+    }
+
+    /// 裏画面を表画面へ反映し、裏画面を消去する(00E0)
+    ///
+    pub fn present(&mut self) {
+        self.front.copy_from_slice(&self.back);
+        self.front_idx.copy_from_slice(&self.back_idx);
+        self.back.fill(0);
+        self.back_idx.fill(0);
+    }
+
+    /// 両画面を消去する
+    ///
+    pub fn clear(&mut self) {
+        self.back.fill(0);
+        self.back_idx.fill(0);
+        self.front.fill(0);
+        self.front_idx.fill(0);
+    }
+
+    /// メモリ上の nn 色分(1色4バイト ARGB)をパレット1番〜へ読み込む(02nn)
+    ///
+    pub fn load_palette(&mut self, mem: &[u8], addr: usize, count: usize) {
+        for i in 0..count {
+            let p = addr + i * 4;
+            self.palette[(i + 1) & 0xFF] = u32::from_be_bytes([
+                mem[p % mem.len()],
+                mem[(p + 1) % mem.len()],
+                mem[(p + 2) % mem.len()],
+                mem[(p + 3) % mem.len()],
+            ]);
+        }
+    }
+
+    /// (x, y) に I の位置のスプライトを裏画面へ描画し、衝突したら true を返す
+    ///
+    pub fn draw_sprite(&mut self, mem: &[u8], addr: usize, x: usize, y: usize) -> bool {
+        let mut collision = false;
+
+        for sy in 0..self.sprite_h {
+            let py = y + sy;
+            if py >= Self::YSIZE {
+                break;
+            }
+            for sx in 0..self.sprite_w {
+                let px = x + sx;
+                if px >= Self::XSIZE {
+                    break;
+                }
+
+                // 0番は透明
+                let idx = mem[(addr + sy * self.sprite_w + sx) % mem.len()];
+                if idx == 0 {
+                    continue;
+                }
+
+                let pos = py * Self::XSIZE + px;
+                if self.collision != 0 && self.back_idx[pos] == self.collision {
+                    collision = true;
+                }
+                self.back[pos] = self.blend_color(self.back[pos], self.palette[idx as usize]);
+                self.back_idx[pos] = idx;
+            }
+        }
+
+        collision
+    }
+
+    /// 合成方法に従って、dst に src を重ねた色を返す
+    ///
+    fn blend_color(&self, dst: u32, src: u32) -> u32 {
+        let mut rgb = 0;
+        for shift in [16, 8, 0] {
+            let d = (dst >> shift) & 0xFF;
+            let s = (src >> shift) & 0xFF;
+            let c = match self.blend {
+                Self::BLEND_25 => (d * 3 + s) / 4,
+                Self::BLEND_50 => (d + s) / 2,
+                Self::BLEND_75 => (d + s * 3) / 4,
+                Self::BLEND_ADD => (d + s).min(0xFF),
+                Self::BLEND_MUL => d * s / 0xFF,
+                _ => s,
+            };
+            rgb |= c << shift;
+        }
+        rgb
+    }
+
+    /// 表画面の (x, y) の表示色(画面全体の不透明度を反映)
+    ///
+    pub fn pixel_color(&self, x: usize, y: usize) -> Color {
+        let rgb = self.front[y * Self::XSIZE + x];
+        let fade = |c: u32| ((c & 0xFF) * self.alpha as u32 / 0xFF) as u8;
+        Color::RGB(fade(rgb >> 16), fade(rgb >> 8), fade(rgb))
+    }
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
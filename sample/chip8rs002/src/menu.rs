@@ -0,0 +1,417 @@
+//
+// ウインドウ内のメニュー
+//
+//   F1(ゲームパッドは BACK ボタン)で開き、キーボードの矢印キー/Enter/Esc か、
+//   ゲームパッドの十字キー/A/B で操作する。メニューを開いている間は実行を止める。
+//     ROM の選択: ディレクトリを辿って ROM を選ぶ(ROM データベースにあればタイトルを表示)
+//     設定      : 互換動作、実行速度、パレット、拡大方法、キー割り当てをその場で変える
+//
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::controller::Button;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::font;
+use crate::romdb::RomDb;
+
+/// ROM イメージとみなす拡張子
+const ROM_EXTENSIONS: [&str; 8] = ["ch8", "c8", "c8x", "c10", "hc8", "mc8", "sc8", "xo8"];
+
+/// メインページの項目
+const MAIN_ITEMS: [&str; 4] = ["Resume", "Load ROM", "Settings", "Quit"];
+
+const BACK_COLOR: Color = Color::RGBA(0, 0, 0, 208); // 背景の色
+const TITLE_COLOR: Color = Color::RGBA(255, 204, 0, 255); // 見出しの色
+const TEXT_COLOR: Color = Color::RGBA(255, 255, 255, 255); // 項目の色
+const DIR_COLOR: Color = Color::RGBA(128, 192, 255, 255); // ディレクトリの色
+const CURSOR_COLOR: Color = Color::RGBA(80, 80, 160, 255); // 選択中の項目の背景の色
+
+/// メニューで変えられる設定
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    Quirks,
+    Speed,
+    Palette,
+    Scale,
+    Keymap,
+}
+
+impl Setting {
+    /// 表示名
+    ///
+    fn label(&self) -> &'static str {
+        match self {
+            Setting::Quirks => "Quirks",
+            Setting::Speed => "Speed",
+            Setting::Palette => "Palette",
+            Setting::Scale => "Scale",
+            Setting::Keymap => "Keymap",
+        }
+    }
+}
+
+/// メニューの操作の結果
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// メニューを閉じて実行を続ける
+    Resume,
+    /// ROM を読み込む
+    Load(PathBuf),
+    /// 設定を変える (設定, 値)
+    Set(Setting, String),
+    /// キーの割り当て直しを始める
+    Rebind,
+    /// 終了する
+    Quit,
+}
+
+/// メニューの操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Nav {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Back,
+    Toggle,
+}
+
+/// ページ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Page {
+    Main,
+    Browser,
+    Settings,
+}
+
+/// ROM の選択の項目
+struct Entry {
+    label: String, // 表示名(ROM データベースのタイトル または ファイル名)
+    path: PathBuf, // パス
+    dir: bool,     // ディレクトリか
+}
+
+/// 設定の項目
+struct Choice {
+    setting: Setting,    // 設定
+    values: Vec<String>, // 選べる値
+    current: usize,      // 選んでいる値
+}
+
+pub struct Menu {
+    pub open: bool,       // 開いているか
+    page: Page,           // 表示中のページ
+    cursor: usize,        // 選択中の項目
+    dir: PathBuf,         // ROM の選択で表示中のディレクトリ
+    entries: Vec<Entry>,  // ROM の選択の項目
+    choices: Vec<Choice>, // 設定の項目
+}
+
+impl Menu {
+    /// dir: ROM の選択で最初に表示するディレクトリ
+    ///
+    pub fn new(dir: PathBuf) -> Menu {
+        Menu {
+            open: false,
+            page: Page::Main,
+            cursor: 0,
+            dir,
+            entries: Vec::new(),
+            choices: Vec::new(),
+        }
+    }
+
+    /// 設定の項目を登録する(登録済みなら選べる値と現在の値を置き換える)
+    ///
+    pub fn set_choice(&mut self, setting: Setting, values: Vec<String>, current: &str) {
+        let mut values = values;
+        if !values.iter().any(|v| v == current) {
+            values.push(current.to_string());
+        }
+        let current = values.iter().position(|v| v == current).unwrap_or(0);
+        let choice = Choice {
+            setting,
+            values,
+            current,
+        };
+        match self.choices.iter_mut().find(|c| c.setting == setting) {
+            Some(dst) => *dst = choice,
+            None => self.choices.push(choice),
+        }
+    }
+
+    /// メインページを開く
+    ///
+    pub fn show(&mut self) {
+        self.open = true;
+        self.page = Page::Main;
+        self.cursor = 0;
+    }
+
+    /// ROM の選択を開く
+    ///
+    pub fn show_browser(&mut self, db: Option<&RomDb>) {
+        self.open = true;
+        let dir = self.dir.clone();
+        self.enter_dir(&dir, db);
+    }
+
+    /// ディレクトリの内容を読み込む
+    ///   ディレクトリを先に、それぞれ名前順に並べる
+    fn enter_dir(&mut self, dir: &Path, db: Option<&RomDb>) {
+        self.page = Page::Browser;
+        self.cursor = 0;
+        self.dir = dir.canonicalize().unwrap_or(dir.to_path_buf());
+        self.entries.clear();
+
+        if let Some(parent) = self.dir.parent() {
+            self.entries.push(Entry {
+                label: "..".to_string(),
+                path: parent.to_path_buf(),
+                dir: true,
+            });
+        }
+
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut dirs = Vec::new();
+        let mut roms = Vec::new();
+        for path in read_dir.flatten().map(|e| e.path()) {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if name.starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(Entry {
+                    label: format!("{}/", name),
+                    path,
+                    dir: true,
+                });
+            } else if is_rom(&path) {
+                let title = db
+                    .and_then(|db| fs::read(&path).ok().and_then(|rom| db.lookup(&rom)))
+                    .and_then(|rom| rom.title);
+                let label = match title {
+                    Some(title) => format!("{} ({})", title, name),
+                    None => name,
+                };
+                roms.push(Entry {
+                    label,
+                    path,
+                    dir: false,
+                });
+            }
+        }
+        dirs.sort_by(|a, b| a.path.cmp(&b.path));
+        roms.sort_by(|a, b| a.path.cmp(&b.path));
+        self.entries.extend(dirs);
+        self.entries.extend(roms);
+    }
+
+    /// 表示中のページの項目数
+    ///
+    fn item_count(&self) -> usize {
+        match self.page {
+            Page::Main => MAIN_ITEMS.len(),
+            Page::Browser => self.entries.len(),
+            Page::Settings => self.choices.len() + 1,
+        }
+    }
+
+    /// イベントを処理する
+    ///   メニューを開いている間は全てのキー入力をメニューで受け取る
+    pub fn handle_event(&mut self, event: &Event, db: Option<&RomDb>) -> Option<Action> {
+        let nav = nav(event)?;
+        if !self.open {
+            if nav == Nav::Toggle {
+                self.show();
+            }
+            return None;
+        }
+
+        let count = self.item_count();
+        match nav {
+            Nav::Toggle => {
+                self.open = false;
+                return Some(Action::Resume);
+            }
+            Nav::Up if count > 0 => self.cursor = (self.cursor + count - 1) % count,
+            Nav::Down if count > 0 => self.cursor = (self.cursor + 1) % count,
+            Nav::Back => match self.page {
+                Page::Main => {
+                    self.open = false;
+                    return Some(Action::Resume);
+                }
+                Page::Browser | Page::Settings => {
+                    self.cursor = if self.page == Page::Browser { 1 } else { 2 };
+                    self.page = Page::Main;
+                }
+            },
+            Nav::Left | Nav::Right if self.page == Page::Settings => {
+                let choice = self.choices.get_mut(self.cursor)?;
+                let len = choice.values.len();
+                choice.current = if nav == Nav::Left {
+                    (choice.current + len - 1) % len
+                } else {
+                    (choice.current + 1) % len
+                };
+                return Some(Action::Set(
+                    choice.setting,
+                    choice.values[choice.current].clone(),
+                ));
+            }
+            Nav::Enter => match self.page {
+                Page::Main => match self.cursor {
+                    0 => {
+                        self.open = false;
+                        return Some(Action::Resume);
+                    }
+                    1 => self.show_browser(db),
+                    2 => {
+                        self.page = Page::Settings;
+                        self.cursor = 0;
+                    }
+                    _ => return Some(Action::Quit),
+                },
+                Page::Browser => {
+                    let entry = self.entries.get(self.cursor)?;
+                    if entry.dir {
+                        let dir = entry.path.clone();
+                        self.enter_dir(&dir, db);
+                    } else {
+                        self.open = false;
+                        return Some(Action::Load(entry.path.clone()));
+                    }
+                }
+                Page::Settings => {
+                    // 最後の項目はキーの割り当て直し
+                    if self.cursor == self.choices.len() {
+                        self.open = false;
+                        return Some(Action::Rebind);
+                    }
+                }
+            },
+            _ => {}
+        }
+        None
+    }
+
+    /// メニューを描く
+    ///
+    pub fn draw(&self, canvas: &mut Canvas<Window>) {
+        if !self.open {
+            return;
+        }
+        let (win_w, win_h) = canvas.output_size().unwrap_or((0, 0));
+        let dot = (win_h / 120).max(2);
+        let line = (font::LINE_HEIGHT * dot + dot * 2) as i32;
+        let margin = (dot * 4) as i32;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(BACK_COLOR);
+        let _ = canvas.fill_rect(Rect::new(0, 0, win_w, win_h));
+        canvas.set_blend_mode(BlendMode::None);
+
+        // 見出しと項目
+        let (title, items): (String, Vec<(String, Color)>) = match self.page {
+            Page::Main => (
+                "MENU".to_string(),
+                MAIN_ITEMS
+                    .iter()
+                    .map(|s| (s.to_string(), TEXT_COLOR))
+                    .collect(),
+            ),
+            Page::Browser => (
+                self.dir.display().to_string(),
+                self.entries
+                    .iter()
+                    .map(|e| {
+                        let color = if e.dir { DIR_COLOR } else { TEXT_COLOR };
+                        (e.label.clone(), color)
+                    })
+                    .collect(),
+            ),
+            Page::Settings => (
+                "SETTINGS".to_string(),
+                self.choices
+                    .iter()
+                    .map(|c| {
+                        let text = format!("{:<8} < {} >", c.setting.label(), c.values[c.current]);
+                        (text, TEXT_COLOR)
+                    })
+                    .chain(std::iter::once(("Rebind keys".to_string(), TEXT_COLOR)))
+                    .collect(),
+            ),
+        };
+        font::draw_text(canvas, margin, margin, dot, &title, TITLE_COLOR);
+
+        // 選択中の項目が見えるようにスクロールする
+        let top = margin + line * 2;
+        let rows = ((win_h as i32 - top - margin) / line).max(1) as usize;
+        let first = self.cursor.saturating_sub(rows - 1);
+        for (n, (text, color)) in items.iter().enumerate().skip(first).take(rows) {
+            let y = top + (n - first) as i32 * line;
+            if n == self.cursor {
+                canvas.set_draw_color(CURSOR_COLOR);
+                let _ = canvas.fill_rect(Rect::new(
+                    margin - dot as i32,
+                    y - dot as i32,
+                    win_w.saturating_sub(margin as u32 * 2 - dot * 2),
+                    font::GLYPH_H * dot + dot * 2,
+                ));
+            }
+            font::draw_text(canvas, margin, y, dot, text, *color);
+        }
+    }
+}
+
+/// ROM イメージのファイルか(拡張子で判断する)
+///
+pub fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// イベントをメニューの操作にする
+///
+fn nav(event: &Event) -> Option<Nav> {
+    match *event {
+        Event::KeyDown {
+            keycode: Some(code),
+            ..
+        } => match code {
+            Keycode::Up => Some(Nav::Up),
+            Keycode::Down => Some(Nav::Down),
+            Keycode::Left => Some(Nav::Left),
+            Keycode::Right => Some(Nav::Right),
+            Keycode::Return | Keycode::KpEnter | Keycode::Space => Some(Nav::Enter),
+            Keycode::Escape | Keycode::Backspace => Some(Nav::Back),
+            Keycode::F1 => Some(Nav::Toggle),
+            _ => None,
+        },
+        Event::ControllerButtonDown { button, .. } => match button {
+            Button::DPadUp => Some(Nav::Up),
+            Button::DPadDown => Some(Nav::Down),
+            Button::DPadLeft => Some(Nav::Left),
+            Button::DPadRight => Some(Nav::Right),
+            Button::A => Some(Nav::Enter),
+            Button::B => Some(Nav::Back),
+            Button::Back | Button::Guide => Some(Nav::Toggle),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,206 @@
+//
+// 画面上の表示(OSD)
+//
+//   ウインドウの上に、FPS、1秒あたりの実行命令数、実行速度(実機に対する倍率)、
+//   一時停止中、早送り中、スロー中、動画の記録中の表示と、操作の結果などのメッセージを重ねて描く。
+//   文字は組み込みのビットマップフォント(font.rs)で描く。
+//
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::font;
+
+/// メッセージを表示する時間
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+/// 同時に表示するメッセージの最大数
+const MAX_MESSAGES: usize = 4;
+/// 統計を計算し直す間隔
+const STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+const TEXT_COLOR: Color = Color::RGBA(255, 255, 255, 255); // 文字の色
+const ERROR_COLOR: Color = Color::RGBA(255, 96, 96, 255); // エラーの文字の色
+const BACK_COLOR: Color = Color::RGBA(0, 0, 0, 160); // 文字の背景の色
+
+pub struct Osd {
+    pub show_stats: bool,                        // 統計(FPS など)を表示する
+    pub paused: bool,                            // 一時停止中(表示のみ。状態はスケジューラが持つ)
+    pub rate: f64,                               // 早送り、スローの実行速度の倍率(1.0 で等速)
+    pub recording: bool,                         // 動画を記録中
+    pub status: Option<&'static str>,            // 実行を止めて待っている理由(キー入力待ちなど)
+    frame_hz: f64,                               // 実機のフレームレート [Hz]
+    messages: VecDeque<(String, bool, Instant)>, // メッセージ(文字列, エラーか, 表示を始めた時刻)
+    frames: u32,                                 // 前回の計算からの表示フレーム数
+    ticks: u32,                                  // 前回の計算からの実行フレーム数
+    steps: u64,                                  // 前回の計算からの実行命令数
+    since: Instant,                              // 前回の計算の時刻
+    fps: f64,                                    // 表示フレーム数/秒
+    ips: f64,                                    // 実行命令数/秒
+    speed: f64,                                  // 実機に対する実行速度の倍率
+}
+
+impl Osd {
+    /// frame_ms: 実機の1フレームの時間 [ms]
+    ///
+    pub fn new(show_stats: bool, frame_ms: f64) -> Osd {
+        Osd {
+            show_stats,
+            paused: false,
+            rate: 1.0,
+            recording: false,
+            status: None,
+            frame_hz: 1000.0 / frame_ms.max(1.0),
+            messages: VecDeque::new(),
+            frames: 0,
+            ticks: 0,
+            steps: 0,
+            since: Instant::now(),
+            fps: 0.0,
+            ips: 0.0,
+            speed: 0.0,
+        }
+    }
+
+    /// メッセージを表示する
+    ///
+    pub fn message<S: Into<String>>(&mut self, text: S) {
+        self.push(text.into(), false);
+    }
+
+    /// エラーメッセージを表示する
+    ///
+    pub fn error<S: Into<String>>(&mut self, text: S) {
+        self.push(text.into(), true);
+    }
+
+    fn push(&mut self, text: String, error: bool) {
+        self.messages.push_back((text, error, Instant::now()));
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    /// 実行した命令数を数える
+    ///
+    pub fn count_steps(&mut self, steps: u64) {
+        self.steps += steps;
+    }
+
+    /// 実行したフレーム(タイマーの更新)を数える
+    ///
+    pub fn count_tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// 表示したフレームを数え、一定時間毎に統計を計算し直す
+    ///
+    pub fn count_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.since.elapsed();
+        if elapsed >= STATS_INTERVAL {
+            let secs = elapsed.as_secs_f64();
+            self.fps = self.frames as f64 / secs;
+            self.ips = self.steps as f64 / secs;
+            self.speed = self.ticks as f64 / secs / self.frame_hz;
+            self.frames = 0;
+            self.ticks = 0;
+            self.steps = 0;
+            self.since = Instant::now();
+        }
+    }
+
+    /// OSD を描く
+    ///
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) {
+        self.messages
+            .retain(|(_, _, since)| since.elapsed() < MESSAGE_TIME);
+
+        let (win_w, win_h) = canvas.output_size().unwrap_or((0, 0));
+        let dot = (win_h / 160).max(2);
+        let margin = (dot * 2) as i32;
+        let line = (font::LINE_HEIGHT * dot) as i32;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        // 左上に統計
+        if self.show_stats {
+            let text = format!(
+                "FPS {:.0}  IPS {:.0}  SPEED X{:.2}",
+                self.fps, self.ips, self.speed
+            );
+            label(canvas, margin, margin, dot, &text, TEXT_COLOR);
+        }
+
+        // 上の中央に一時停止中、早送り中、スロー中の表示
+        let state = if self.paused {
+            Some("PAUSED".to_string())
+        } else if self.rate > 1.0 {
+            Some(format!("FAST X{}", self.rate))
+        } else if self.rate < 1.0 {
+            Some(format!("SLOW X{}", self.rate))
+        } else {
+            None
+        };
+        if let Some(text) = state {
+            let (w, _) = font::text_size(&text, dot);
+            label(
+                canvas,
+                (win_w as i32 - w as i32) / 2,
+                margin,
+                dot,
+                &text,
+                TEXT_COLOR,
+            );
+        }
+
+        // 右上に動画の記録中の表示
+        if self.recording {
+            let text = "REC";
+            let (w, _) = font::text_size(text, dot);
+            label(
+                canvas,
+                win_w as i32 - margin - w as i32,
+                margin,
+                dot,
+                text,
+                ERROR_COLOR,
+            );
+        }
+
+        // 左下にメッセージ(新しいものが下)
+        //   統計の表示中は、待っている理由を一番下に出す
+        let status = self.status.filter(|_| self.show_stats);
+        let count = self.messages.len() as i32 + status.is_some() as i32;
+        let lines = self
+            .messages
+            .iter()
+            .map(|(text, error, _)| (text.as_str(), *error))
+            .chain(status.map(|text| (text, false)));
+        for (n, (text, error)) in lines.enumerate() {
+            let y = win_h as i32 - margin - (count - n as i32) * line;
+            let color = if error { ERROR_COLOR } else { TEXT_COLOR };
+            label(canvas, margin, y, dot, text, color);
+        }
+
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+/// 半透明の背景を付けて文字列を描く
+///
+fn label(canvas: &mut Canvas<Window>, x: i32, y: i32, dot: u32, text: &str, color: Color) {
+    let (w, h) = font::text_size(text, dot);
+    canvas.set_draw_color(BACK_COLOR);
+    let _ = canvas.fill_rect(Rect::new(
+        x - dot as i32,
+        y - dot as i32,
+        w + dot * 2,
+        h + dot * 2,
+    ));
+    font::draw_text(canvas, x, y, dot, text, color);
+}
@@ -0,0 +1,170 @@
+//
+// 表示色のパレット
+//
+//   パレットは 4 色で、画素値(0〜3)で色を選ぶ。
+//     0: 背景色、1: ピクセルの色、2〜3: 複数プレーンの画面用(XO-CHIP のプレーン2、両方のプレーン)
+//   組み込みのテーマのほか、設定ファイルの [palettes] で名前を付けたパレットを定義できる。
+//
+//   例)
+//     palette = "mine"
+//
+//     [palettes]
+//     mine = ["#101010", "#e0e0e0", "#808080", "#ffffff"]
+//
+
+use std::collections::BTreeMap;
+
+use sdl2::pixels::Color;
+
+use crate::config;
+
+/// 組み込みのテーマ
+const THEMES: [(&str, [Color; 4]); 5] = [
+    // 緑のモニタ
+    (
+        "classic",
+        [
+            Color::RGB(0, 0, 0),
+            Color::RGB(0, 200, 0),
+            Color::RGB(0, 100, 0),
+            Color::RGB(150, 255, 150),
+        ],
+    ),
+    // アンバーのモニタ
+    (
+        "amber",
+        [
+            Color::RGB(0, 0, 0),
+            Color::RGB(255, 176, 0),
+            Color::RGB(128, 88, 0),
+            Color::RGB(255, 217, 138),
+        ],
+    ),
+    // 液晶(4階調の緑)
+    (
+        "lcd",
+        [
+            Color::RGB(155, 188, 15),
+            Color::RGB(15, 56, 15),
+            Color::RGB(139, 172, 15),
+            Color::RGB(48, 98, 48),
+        ],
+    ),
+    // Octo の既定の色
+    (
+        "octo",
+        [
+            Color::RGB(0x99, 0x66, 0x00),
+            Color::RGB(0xFF, 0xCC, 0x00),
+            Color::RGB(0xFF, 0x66, 0x00),
+            Color::RGB(0x66, 0x22, 0x00),
+        ],
+    ),
+    // 高コントラスト
+    (
+        "high-contrast",
+        [
+            Color::RGB(0, 0, 0),
+            Color::RGB(255, 255, 255),
+            Color::RGB(255, 255, 0),
+            Color::RGB(0, 255, 255),
+        ],
+    ),
+];
+
+/// パレット
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,       // パレット名
+    pub colors: [Color; 4], // 画素値 0〜3 の色
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::theme(THEMES[0].0).unwrap()
+    }
+}
+
+impl Palette {
+    /// 組み込みのテーマ名一覧
+    pub const THEMES: [&'static str; 5] = ["classic", "amber", "lcd", "octo", "high-contrast"];
+
+    /// 組み込みのテーマ
+    ///
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(name, colors)| Palette {
+                name: name.to_string(),
+                colors: *colors,
+            })
+    }
+
+    /// "#rrggbb" の並び(2〜4色)から作る
+    ///   3色目、4色目が無ければピクセルの色を使う
+    pub fn from_strings(name: &str, colors: &[String]) -> Result<Palette, String> {
+        if !(2..=4).contains(&colors.len()) {
+            return Err(format!(
+                "Palette '{}' needs 2 to 4 colours (got {})",
+                name,
+                colors.len()
+            ));
+        }
+        let mut palette = [Color::RGB(0, 0, 0); 4];
+        for (i, dst) in palette.iter_mut().enumerate() {
+            let s = colors.get(i).unwrap_or(&colors[1]);
+            *dst =
+                config::parse_color(s).ok_or(format!("Invalid colour: '{}' (use #rrggbb)", s))?;
+        }
+        Ok(Palette {
+            name: name.to_string(),
+            colors: palette,
+        })
+    }
+
+    /// 名前からパレットを探す(設定ファイルのパレットを優先する)
+    ///
+    pub fn find(name: &str, user: &BTreeMap<String, Vec<String>>) -> Result<Palette, String> {
+        match user.get(name) {
+            Some(colors) => Palette::from_strings(name, colors),
+            None => Palette::theme(name).ok_or(format!(
+                "Unknown palette: '{}' (available: {})",
+                name,
+                Palette::names(user).join(", ")
+            )),
+        }
+    }
+
+    /// 組み込みのテーマと設定ファイルのパレットの名前一覧
+    ///
+    pub fn names(user: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+        let mut names: Vec<String> = Palette::THEMES.iter().map(|s| s.to_string()).collect();
+        for name in user.keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// 背景色とピクセルの色を置き換える
+    ///
+    pub fn with_colors(mut self, (bg, fg): (Color, Color)) -> Palette {
+        self.colors[0] = bg;
+        self.colors[1] = fg;
+        self
+    }
+
+    /// (背景色, ピクセルの色)
+    ///
+    pub fn colors(&self) -> (Color, Color) {
+        (self.colors[0], self.colors[1])
+    }
+
+    /// 画素値の色
+    ///
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[(pixel & 3) as usize]
+    }
+}
@@ -0,0 +1,178 @@
+//
+// CHIP-8 のプラットフォーム(派生仕様)
+//
+//   プラットフォーム毎に、プログラムの読み込みアドレスや拡張命令、
+//   既定の互換動作(Quirks)が異なる。
+//
+
+use crate::quirks::Quirks;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// オリジナルの CHIP-8
+    Chip8,
+    /// CHIP-8X (COSMAC VIP + VP-590 カラーボード、VP-580 拡張キーパッド)
+    Chip8X,
+    /// 64x64 の2ページ hi-res CHIP-8
+    HiRes,
+    /// 128x64 の CHIP-10
+    Chip10,
+    /// MegaChip8 (256x192、256色、16MB アドレス空間)
+    MegaChip8,
+    /// ETI-660 (64x48、0x600 から読み込み)
+    Eti660,
+    /// DREAM 6800 (CHIPOS)
+    Dream6800,
+}
+
+impl Platform {
+    /// プラットフォーム名一覧
+    pub const NAMES: [&'static str; 7] = [
+        "chip8",
+        "chip8x",
+        "hires",
+        "chip10",
+        "megachip8",
+        "eti660",
+        "dream6800",
+    ];
+
+    /// プラットフォーム名から取得
+    ///
+    pub fn from_name<T: AsRef<str>>(name: T) -> Option<Platform> {
+        match name.as_ref() {
+            "chip8" => Some(Platform::Chip8),
+            "chip8x" => Some(Platform::Chip8X),
+            "hires" => Some(Platform::HiRes),
+            "chip10" => Some(Platform::Chip10),
+            "megachip8" => Some(Platform::MegaChip8),
+            "eti660" => Some(Platform::Eti660),
+            "dream6800" => Some(Platform::Dream6800),
+            _ => None,
+        }
+    }
+
+    /// プラットフォーム名
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Chip8X => "chip8x",
+            Platform::HiRes => "hires",
+            Platform::Chip10 => "chip10",
+            Platform::MegaChip8 => "megachip8",
+            Platform::Eti660 => "eti660",
+            Platform::Dream6800 => "dream6800",
+        }
+    }
+
+    /// プログラムの読み込みアドレス
+    ///
+    pub fn load_address(&self) -> usize {
+        match self {
+            Platform::Chip8
+            | Platform::HiRes
+            | Platform::Chip10
+            | Platform::MegaChip8
+            | Platform::Dream6800 => 0x200,
+            // CHIP-8X はインタプリタが大きいため 0x300 から
+            Platform::Chip8X => 0x300,
+            // ETI-660 は 0x000〜0x5FF をインタプリタが使う
+            Platform::Eti660 => 0x600,
+        }
+    }
+
+    /// 実行開始アドレス
+    ///
+    pub fn entry_point(&self) -> usize {
+        match self {
+            // hi-res CHIP-8 の ROM は先頭に 1260(インタプリタ拡張部へのジャンプ)を持ち、
+            // 本体は 0x2C0 から始まる
+            Platform::HiRes => 0x2C0,
+            _ => self.load_address(),
+        }
+    }
+
+    /// メインメモリのサイズ
+    ///
+    pub fn mem_size(&self) -> usize {
+        match self {
+            // 01nn nnnn で 24bit アドレスを扱う
+            Platform::MegaChip8 => 0x100_0000,
+            _ => 0x1000,
+        }
+    }
+
+    /// 画面サイズ (横ピクセル数, 縦ライン数)
+    ///   MegaChip8 は 0011 までの 64x32(MegaChip モードでは 256x192 になる)
+    pub fn display_size(&self) -> (usize, usize) {
+        match self {
+            Platform::Chip8 | Platform::Chip8X | Platform::Dream6800 | Platform::MegaChip8 => {
+                (64, 32)
+            }
+            Platform::Eti660 => (64, 48),
+            Platform::HiRes => (64, 64),
+            Platform::Chip10 => (128, 64),
+        }
+    }
+
+    /// 既定の互換動作
+    ///
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8
+            | Platform::Chip10
+            | Platform::MegaChip8
+            | Platform::Eti660
+            | Platform::Dream6800 => Quirks::default(),
+            // CHIP-8X と hi-res CHIP-8 は COSMAC VIP 上で動作する
+            Platform::Chip8X | Platform::HiRes => Quirks::from_profile("vip").unwrap_or_default(),
+        }
+    }
+
+    /// 16進数字フォント(Fx29)の配置アドレス
+    ///
+    pub fn font_address(&self) -> usize {
+        match self {
+            // 読み込みアドレスより前のインタプリタ領域に置く
+            Platform::Eti660 => 0x500,
+            // CHIPOS は 0x100〜0x1FF を表示用バッファに使うので、その手前に置く
+            Platform::Dream6800 => 0x080,
+            _ => 0x000,
+        }
+    }
+
+    /// キーボード上の 4x4 の位置(1234/QWER/ASDF/ZXCV)に対応するキー値
+    ///
+    pub fn keypad_layout(&self) -> [u8; 16] {
+        match self {
+            // DREAM 6800 のキーパッド
+            //   | C | D | E | F |
+            //   | 8 | 9 | A | B |
+            //   | 4 | 5 | 6 | 7 |
+            //   | 0 | 1 | 2 | 3 |
+            Platform::Dream6800 => [
+                0xC, 0xD, 0xE, 0xF, 0x8, 0x9, 0xA, 0xB, 0x4, 0x5, 0x6, 0x7, 0x0, 0x1, 0x2, 0x3,
+            ],
+            // COSMAC VIP のキーパッド
+            //   | 1 | 2 | 3 | C |
+            //   | 4 | 5 | 6 | D |
+            //   | 7 | 8 | 9 | E |
+            //   | A | 0 | B | F |
+            _ => [
+                0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+            ],
+        }
+    }
+
+    /// タイマー(DT/ST)を1つ減らす間隔 [us]
+    ///   ミリ秒単位では 60Hz を表せないのでマイクロ秒で返す
+    pub fn timer_cycle_us(&self) -> i32 {
+        match self {
+            // オーストラリア製(PAL地域)の機種は 50Hz
+            Platform::Eti660 | Platform::Dream6800 => 20_000,
+            // 60Hz (16.667ms)
+            _ => 1_000_000 / 60,
+        }
+    }
+}
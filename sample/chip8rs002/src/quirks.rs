@@ -0,0 +1,58 @@
+//
+// CHIP-8 互換動作(Quirks)の設定
+//
+//   実機(COSMAC VIPなど)と後発のインタプリタでは、
+//   一部の命令の振る舞いが異なる。ROMによって期待する動作が違うため、
+//   プロファイルとして切り替えられるようにする。
+//
+
+/// 互換動作の設定
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Dxyn 実行時に次のフレーム境界(60Hz)まで待つ
+    /// (COSMAC VIPは垂直帰線期間を待ってから描画する)
+    pub display_wait: bool,
+
+    /// 8xy6/8xyE で Vy をシフトした結果を Vx に入れる
+    /// (COSMAC VIP の動作。false では Vx 自身をシフトし、Vy は使わない)
+    pub shift_vy: bool,
+
+    /// Fx55/Fx65 の実行後に I を x+1 進めるか
+    ///
+    pub memory_increment: MemoryIncrement,
+}
+
+/// Fx55/Fx65 の実行後の I の扱い
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    /// I は変化しない(SCHIP 以降のインタプリタの動作)
+    Unchanged,
+    /// Fx65 だけ I を x+1 進める(このエミュレータの従来の動作)
+    #[default]
+    LoadOnly,
+    /// Fx55/Fx65 とも I を x+1 進める(COSMAC VIP の動作)
+    Both,
+    /// Fx55/Fx65 とも I を x 進める(CHIP-48 の動作)
+    ByX,
+}
+
+impl Quirks {
+    /// プロファイル名一覧
+    pub const PROFILES: [&'static str; 2] = ["default", "vip"];
+
+    /// プロファイル名から設定を取得
+    ///
+    pub fn from_profile<T: AsRef<str>>(name: T) -> Option<Quirks> {
+        match name.as_ref() {
+            // 現代的なインタプリタの動作(待ち無し)
+            "default" => Some(Quirks::default()),
+            // オリジナルのCOSMAC VIP
+            "vip" => Some(Quirks {
+                display_wait: true,
+                shift_vy: true,
+                memory_increment: MemoryIncrement::Both,
+            }),
+            _ => None,
+        }
+    }
+}
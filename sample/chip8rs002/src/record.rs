@@ -0,0 +1,208 @@
+//
+// プレイ動画の記録(アニメーション GIF / APNG)
+//
+//   実行したフレーム毎の表示内容を記録し、記録の終わりにまとめてファイルに書き出す。
+//   前のフレームと同じ内容のフレームは記録せず、前のフレームの表示時間を延ばす(ファイルを小さくする)。
+//   記録するのは実機の解像度の画像で、表示の大きさで書き出す場合は、書き出すときに拡大する。
+//     実機の解像度 : パレットの色のまま
+//     表示の大きさ : ちらつき対策、ドット絵向けの拡大、ブラウン管風の効果をかける
+//
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use sdl2::pixels::Color;
+
+use crate::capture::{self, Scale};
+use crate::crt::CrtEffects;
+use crate::filter::DisplayFilter;
+
+/// 書き出す形式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gif,
+    Apng,
+}
+
+impl Format {
+    /// 形式の名前一覧
+    pub const NAMES: [&'static str; 2] = ["gif", "apng"];
+
+    /// 名前から取得
+    ///
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "gif" => Some(Format::Gif),
+            "apng" => Some(Format::Apng),
+            _ => None,
+        }
+    }
+
+    /// 名前
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Gif => "gif",
+            Format::Apng => "apng",
+        }
+    }
+
+    /// 拡張子
+    ///
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Gif => "gif",
+            Format::Apng => "png",
+        }
+    }
+
+    /// ファイル名の拡張子から取得(.gif, .png, .apng)
+    ///
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Format, String> {
+        let path = path.as_ref();
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "gif" => Ok(Format::Gif),
+            "png" | "apng" => Ok(Format::Apng),
+            _ => Err(format!(
+                "Unknown recording format: '{}' (use .gif, .png or .apng)",
+                path.display()
+            )),
+        }
+    }
+}
+
+pub struct Recorder {
+    path: PathBuf,                               // 書き出すファイル
+    format: Format,                              // 書き出す形式
+    frame_ms: f64,                               // 1フレームの時間 [ms]
+    filter: Option<DisplayFilter>,               // ちらつき対策(実機の解像度なら None)
+    frames: Vec<(Vec<u8>, (usize, usize), u32)>, // (画像, 大きさ, 続けて表示するフレーム数)
+    count: u32,                                  // 記録したフレーム数(同じ内容のフレームも数える)
+}
+
+impl Recorder {
+    /// 記録を始める
+    ///
+    ///   frame_ms: 1フレームの時間 [ms]
+    ///   filter  : 表示の大きさで書き出す場合のちらつき対策
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        frame_ms: f64,
+        filter: Option<DisplayFilter>,
+    ) -> Result<Recorder, String> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Recorder {
+            format: Format::from_path(&path)?,
+            path,
+            frame_ms,
+            filter,
+            frames: Vec::new(),
+            count: 0,
+        })
+    }
+
+    /// 書き出すファイル
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 記録したフレーム数
+    ///
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// 1フレーム分の表示内容(RGB24)を記録する
+    ///
+    ///   background: 背景色(ちらつき対策で使う)
+    pub fn push(&mut self, rgb: &[u8], size: (usize, usize), background: Color) {
+        let mut rgb = rgb.to_vec();
+        if let Some(filter) = self.filter.as_mut() {
+            filter.apply(&mut rgb, background);
+        }
+        self.count += 1;
+
+        // 前のフレームと同じなら表示時間を延ばす
+        if let Some((last, last_size, frames)) = self.frames.last_mut() {
+            if *last == rgb && *last_size == size {
+                *frames += 1;
+                return;
+            }
+        }
+        self.frames.push((rgb, size, 1));
+    }
+
+    /// ファイルに書き出す
+    ///
+    ///   scale: 表示の大きさで書き出す場合の拡大(None なら実機の解像度)
+    pub fn finish(self, scale: Option<&Scale>) -> Result<(), String> {
+        let Some((_, first_size, _)) = self.frames.first() else {
+            return Err("No frames recorded".into());
+        };
+
+        // 途中で解像度が変わった場合は、最初のフレームの大きさに揃える
+        let size = scale.map_or(*first_size, |scale| scale.size);
+        let crt = CrtEffects::default();
+        let images = self.frames.iter().map(|(rgb, src_size, frames)| {
+            let image = match scale {
+                Some(scale) => scale.apply(rgb, *src_size),
+                None if src_size == first_size => rgb.clone(),
+                None => capture::display_image(rgb, *src_size, size, &crt),
+            };
+            (image, *frames as f64 * self.frame_ms)
+        });
+
+        let path = &self.path;
+        let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| error(&e))?;
+        }
+        let file = BufWriter::new(File::create(path).map_err(|e| error(&e))?);
+
+        match self.format {
+            Format::Gif => {
+                let mut encoder = gif::Encoder::new(file, size.0 as u16, size.1 as u16, &[])
+                    .map_err(|e| error(&e))?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|e| error(&e))?;
+
+                // GIF の表示時間は 1/100 秒単位なので、端数は次のフレームに繰り越す
+                let mut elapsed_ms = 0.0;
+                let mut written_cs = 0;
+                for (image, ms) in images {
+                    elapsed_ms += ms;
+                    let delay = (elapsed_ms / 10.0).round() as u64 - written_cs;
+                    written_cs += delay;
+                    let mut frame =
+                        gif::Frame::from_rgb_speed(size.0 as u16, size.1 as u16, &image, 10);
+                    frame.delay = delay.min(u16::MAX as u64) as u16;
+                    encoder.write_frame(&frame).map_err(|e| error(&e))?;
+                }
+            }
+            Format::Apng => {
+                let mut encoder = png::Encoder::new(file, size.0 as u32, size.1 as u32);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder
+                    .set_animated(self.frames.len() as u32, 0)
+                    .map_err(|e| error(&e))?;
+                let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+                for (image, ms) in images {
+                    writer
+                        .set_frame_delay(ms.round().min(u16::MAX as f64) as u16, 1000)
+                        .map_err(|e| error(&e))?;
+                    writer.write_image_data(&image).map_err(|e| error(&e))?;
+                }
+                writer.finish().map_err(|e| error(&e))?;
+            }
+        }
+        Ok(())
+    }
+}
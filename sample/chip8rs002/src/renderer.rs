@@ -0,0 +1,161 @@
+//
+// 画面の描画
+//
+//   表示内容を RGB のバッファに変換してストリーミングテクスチャに転送し、
+//   拡大は SDL のレンダラに任せる(画素毎の fill_rect はしない)。
+//   前回と同じ内容ならテクスチャへの転送を省く。
+//   ドット絵向けの拡大アルゴリズムを選んだときは、拡大した画像を転送する。
+//   ブラウン管風の効果を使うときは、表示する大きさの画像を CPU で作って転送する。
+//
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use crate::capture;
+use crate::crt::CrtEffects;
+use crate::filter::DisplayFilter;
+use crate::machine::Machine;
+use crate::upscale::Upscaler;
+
+/// 1画素あたりのバイト数(RGB24)
+const BYTES_PER_PIXEL: usize = 3;
+
+pub struct Renderer<'a> {
+    creator: &'a TextureCreator<WindowContext>, // テクスチャの作成元
+    texture: Option<Texture<'a>>,               // 表示内容を転送するテクスチャ
+    size: (usize, usize),                       // テクスチャの大きさ (横, 縦)
+    pixels: Vec<u8>,                            // 表示内容(RGB24)
+    work: Vec<u8>,                              // 変換用の作業バッファ
+    dirty: bool,                                // テクスチャへの転送が必要
+    pub filter: DisplayFilter,                  // ちらつき対策のフィルタ
+    pub upscaler: Upscaler,                     // ドット絵向けの拡大アルゴリズム
+    crt: CrtEffects,                            // ブラウン管風の効果
+    crt_texture: Option<Texture<'a>>,           // 効果をかけた画像を転送するテクスチャ
+    crt_size: (usize, usize),                   // 効果をかけた画像の大きさ (横, 縦)
+    crt_pixels: Vec<u8>,                        // 効果をかけた画像(RGB24)
+    crt_dirty: bool,                            // 効果をかけ直す必要がある
+}
+
+impl<'a> Renderer<'a> {
+    /// 拡大は最近傍補間(ドットをぼかさない)
+    ///
+    pub fn new(
+        creator: &'a TextureCreator<WindowContext>,
+        filter: DisplayFilter,
+        upscaler: Upscaler,
+        crt: CrtEffects,
+    ) -> Renderer<'a> {
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        Renderer {
+            creator,
+            texture: None,
+            size: (0, 0),
+            pixels: Vec::new(),
+            work: Vec::new(),
+            dirty: true,
+            filter,
+            upscaler,
+            crt,
+            crt_texture: None,
+            crt_size: (0, 0),
+            crt_pixels: Vec::new(),
+            crt_dirty: true,
+        }
+    }
+
+    /// 表示内容を取り込む
+    ///   内容が変わっていれば次の draw() でテクスチャに転送する
+    pub fn update(&mut self, machine: &dyn Machine) -> Result<(), String> {
+        let mut size = capture::frame_rgb(machine, &mut self.work);
+        self.filter
+            .apply(&mut self.work, machine.background_color());
+        if self.upscaler != Upscaler::None {
+            (self.work, size) = self.upscaler.apply(&self.work, size);
+        }
+
+        // 解像度(拡大後の大きさ)が変わったらテクスチャを作り直す
+        if self.texture.is_none() || self.size != size {
+            let texture = self
+                .creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, size.0 as u32, size.1 as u32)
+                .map_err(|e| e.to_string())?;
+            self.texture = Some(texture);
+            self.size = size;
+            self.pixels.clear();
+        }
+
+        if self.work != self.pixels {
+            std::mem::swap(&mut self.work, &mut self.pixels);
+            self.dirty = true;
+            self.crt_dirty = true;
+        }
+        Ok(())
+    }
+
+    /// 表示内容を area 全体に拡大して描画する
+    ///
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, area: Rect) -> Result<(), String> {
+        if self.crt.enabled {
+            return self.draw_crt(canvas, area);
+        }
+
+        let Some(texture) = self.texture.as_mut() else {
+            return Ok(());
+        };
+        if self.dirty {
+            texture
+                .update(None, &self.pixels, self.size.0 * BYTES_PER_PIXEL)
+                .map_err(|e| e.to_string())?;
+            self.dirty = false;
+        }
+        canvas.copy(texture, None, area)
+    }
+
+    /// 表示している画像(RGB24)を size の大きさで取得する(スクリーンショット用)
+    ///
+    pub fn image(&self, size: (usize, usize)) -> Vec<u8> {
+        capture::display_image(&self.pixels, self.size, size, &self.crt)
+    }
+
+    /// ブラウン管風の効果
+    ///
+    pub fn crt(&self) -> CrtEffects {
+        self.crt
+    }
+
+    /// ブラウン管風の効果を変える
+    ///
+    pub fn set_crt(&mut self, crt: CrtEffects) {
+        self.crt = crt;
+        self.crt_dirty = true;
+    }
+
+    /// ブラウン管風の効果をかけて area に描画する
+    ///   area の大きさの画像を作るので、内容か大きさが変わったときだけ作り直す
+    fn draw_crt(&mut self, canvas: &mut Canvas<Window>, area: Rect) -> Result<(), String> {
+        let size = (area.width() as usize, area.height() as usize);
+        if self.crt_texture.is_none() || self.crt_size != size {
+            let texture = self
+                .creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, size.0 as u32, size.1 as u32)
+                .map_err(|e| e.to_string())?;
+            self.crt_texture = Some(texture);
+            self.crt_size = size;
+            self.crt_dirty = true;
+        }
+        let Some(texture) = self.crt_texture.as_mut() else {
+            return Ok(());
+        };
+        if self.crt_dirty {
+            self.crt
+                .process(&self.pixels, self.size, &mut self.crt_pixels, size);
+            texture
+                .update(None, &self.crt_pixels, size.0 * BYTES_PER_PIXEL)
+                .map_err(|e| e.to_string())?;
+            self.crt_dirty = false;
+        }
+        canvas.copy(texture, None, area)
+    }
+}
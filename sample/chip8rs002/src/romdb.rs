@@ -0,0 +1,395 @@
+//
+// ROM データベース
+//
+//   ROMイメージの SHA-1 から、プラットフォーム、互換動作、実行速度、
+//   キー割り当て、表示色、タイトルを調べる。
+//   形式はコミュニティの chip-8-database (sha1-hashes.json / programs.json /
+//   platforms.json) と同じで、同梱版の代わりに設定ディレクトリへ置いた
+//   最新版を読み込むこともできる。
+//   同梱版の出所と更新方法は db/README.md を参照。
+//
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::pixels::Color;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::config;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+
+// 同梱のデータベース
+const BUNDLED_HASHES: &str = include_str!("../db/sha1-hashes.json");
+const BUNDLED_PROGRAMS: &str = include_str!("../db/programs.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../db/platforms.json");
+
+/// programs.json の1プログラム
+#[derive(Deserialize)]
+struct DbProgram {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    roms: HashMap<String, DbRom>,
+}
+
+/// programs.json の ROM毎の情報
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DbRom {
+    #[serde(default)]
+    embedded_title: Option<String>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, DbQuirks>,
+    #[serde(default)]
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    colors: Option<DbColors>,
+}
+
+/// 互換動作
+///   wrap / jump / logic はこのエミュレータでは切り替えられない(lookup で報告する)
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+struct DbQuirks {
+    #[serde(default)]
+    vblank: Option<bool>,
+    #[serde(default)]
+    shift: Option<bool>,
+    #[serde(default)]
+    memory_leave_i_unchanged: Option<bool>,
+    #[serde(default)]
+    memory_increment_by_x: Option<bool>,
+    #[serde(default)]
+    wrap: Option<bool>,
+    #[serde(default)]
+    jump: Option<bool>,
+    #[serde(default)]
+    logic: Option<bool>,
+}
+
+impl DbQuirks {
+    /// 指定のある項目だけ other で上書きする
+    ///
+    fn overlay(self, other: &DbQuirks) -> DbQuirks {
+        DbQuirks {
+            vblank: other.vblank.or(self.vblank),
+            shift: other.shift.or(self.shift),
+            memory_leave_i_unchanged: other
+                .memory_leave_i_unchanged
+                .or(self.memory_leave_i_unchanged),
+            memory_increment_by_x: other.memory_increment_by_x.or(self.memory_increment_by_x),
+            wrap: other.wrap.or(self.wrap),
+            jump: other.jump.or(self.jump),
+            logic: other.logic.or(self.logic),
+        }
+    }
+}
+
+/// 表示色
+#[derive(Deserialize)]
+struct DbColors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// platforms.json の1プラットフォーム
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DbPlatform {
+    id: String,
+    #[serde(default)]
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: DbQuirks,
+}
+
+/// データベースから分かった ROM の設定
+#[derive(Debug, Default)]
+pub struct RomSettings {
+    pub title: Option<String>,                 // タイトル
+    pub platform_id: Option<String>,           // データベース上のプラットフォームID
+    pub platform: Option<Platform>,            // プラットフォーム
+    pub quirks: Option<Quirks>,                // 互換動作
+    pub tickrate: Option<u32>,                 // 1フレーム(60Hz)あたりの実行命令数
+    pub keys: Vec<(String, u8)>,               // 操作名(up, down, left, right, a, b ...)とキー値
+    pub colors: Option<(Color, Color)>,        // (背景色, 前景色)
+    pub unsupported_quirks: Vec<&'static str>, // 再現できない互換動作(データベースの項目名)
+}
+
+pub struct RomDb {
+    hashes: HashMap<String, usize>, // SHA-1 -> programs の番号
+    programs: Vec<DbProgram>,
+    platforms: Vec<DbPlatform>,
+}
+
+impl RomDb {
+    /// 同梱のデータベース
+    ///
+    pub fn bundled() -> RomDb {
+        Self::parse(BUNDLED_HASHES, BUNDLED_PROGRAMS, BUNDLED_PLATFORMS)
+            .expect("bundled ROM database is broken")
+    }
+
+    /// ディレクトリに置いたデータベースを読み込む
+    ///
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<RomDb, String> {
+        let read = |name: &str| {
+            let path = dir.as_ref().join(name);
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        Self::parse(
+            &read("sha1-hashes.json")?,
+            &read("programs.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    /// 更新版のデータベースを置くディレクトリ
+    ///   $XDG_CONFIG_HOME/chip8rs/chip-8-database (既定は ~/.config/chip8rs/chip-8-database)
+    pub fn user_dir() -> Option<PathBuf> {
+        config::config_dir().map(|dir| dir.join("chip-8-database"))
+    }
+
+    fn parse(hashes: &str, programs: &str, platforms: &str) -> Result<RomDb, String> {
+        let err = |name: &str, e: serde_json::Error| format!("{}: {}", name, e);
+        Ok(RomDb {
+            hashes: serde_json::from_str(hashes).map_err(|e| err("sha1-hashes.json", e))?,
+            programs: serde_json::from_str(programs).map_err(|e| err("programs.json", e))?,
+            platforms: serde_json::from_str(platforms).map_err(|e| err("platforms.json", e))?,
+        })
+    }
+
+    /// ROMイメージの SHA-1 (16進小文字)
+    ///
+    pub fn sha1_hex(rom: &[u8]) -> String {
+        Sha1::digest(rom)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// ROMイメージをデータベースから探す
+    ///
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomSettings> {
+        let hash = Self::sha1_hex(rom);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let info = program.roms.get(&hash)?;
+
+        let mut settings = RomSettings {
+            title: info.embedded_title.clone().or(program.title.clone()),
+            tickrate: info.tickrate,
+            ..Default::default()
+        };
+
+        // 対応しているプラットフォームのうち、最初に挙がっているものを使う
+        if let Some((id, platform)) = info
+            .platforms
+            .iter()
+            .find_map(|id| Self::platform_from_id(id).map(|p| (id, p)))
+        {
+            let db_platform = self.platforms.iter().find(|p| &p.id == id);
+
+            // 互換動作: プラットフォームの既定値 < データベースのプラットフォーム定義 < ROM固有の指定
+            let db_quirks = [
+                db_platform.map(|p| &p.quirks),
+                info.quirky_platforms.get(id),
+            ]
+            .into_iter()
+            .flatten()
+            .fold(DbQuirks::default(), |acc, q| acc.overlay(q));
+
+            let mut quirks = platform.default_quirks();
+            if let Some(vblank) = db_quirks.vblank {
+                quirks.display_wait = vblank;
+            }
+            // データベースの shift は「Vy を使わない」
+            if let Some(shift) = db_quirks.shift {
+                quirks.shift_vy = !shift;
+            }
+            // I を変えない指定が x 進める指定より優先する
+            match (
+                db_quirks.memory_leave_i_unchanged,
+                db_quirks.memory_increment_by_x,
+            ) {
+                (Some(true), _) => quirks.memory_increment = MemoryIncrement::Unchanged,
+                (_, Some(true)) => quirks.memory_increment = MemoryIncrement::ByX,
+                (Some(false), _) | (_, Some(false)) => {
+                    quirks.memory_increment = MemoryIncrement::Both
+                }
+                (None, None) => {}
+            }
+
+            // このエミュレータの動作(スプライトは画面端で回り込む、Bnnn は V0 を足す、
+            // 8xy1/8xy2/8xy3 は VF を変えない)と違う指定は再現できない
+            for (name, wanted, actual) in [
+                ("wrap", db_quirks.wrap, true),
+                ("jump", db_quirks.jump, false),
+                ("logic", db_quirks.logic, false),
+            ] {
+                if wanted.is_some_and(|wanted| wanted != actual) {
+                    settings.unsupported_quirks.push(name);
+                }
+            }
+
+            settings.platform_id = Some(id.clone());
+            settings.platform = Some(platform);
+            settings.quirks = Some(quirks);
+            settings.tickrate = settings
+                .tickrate
+                .or(db_platform.and_then(|p| p.default_tickrate));
+        } else {
+            settings.platform_id = info.platforms.first().cloned();
+        }
+
+        let mut keys: Vec<(String, u8)> = info.keys.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        keys.sort();
+        settings.keys = keys;
+
+        // pixels[0] が背景色、pixels[1] が前景色
+        if let Some(colors) = &info.colors {
+            if let (Some(bg), Some(fg)) = (
+                colors.pixels.first().and_then(|c| config::parse_color(c)),
+                colors.pixels.get(1).and_then(|c| config::parse_color(c)),
+            ) {
+                settings.colors = Some((bg, fg));
+            }
+        }
+
+        Some(settings)
+    }
+
+    /// データベースのプラットフォームIDに対応するプラットフォーム
+    ///
+    fn platform_from_id(id: &str) -> Option<Platform> {
+        match id {
+            // 互換動作の違いは Quirks で表す
+            "originalChip8" | "hybridVIP" | "modernChip8" => Some(Platform::Chip8),
+            "chip8x" => Some(Platform::Chip8X),
+            "megachip8" => Some(Platform::MegaChip8),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IBM Logo.ch8 (132byte)
+    const IBM_LOGO: [u8; 132] = [
+        0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0,
+        0x1F, 0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08,
+        0xA2, 0x66, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF,
+        0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF,
+        0x00, 0x38, 0x00, 0x3F, 0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0,
+        0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC,
+        0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B, 0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07,
+        0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00, 0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0,
+        0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0,
+    ];
+
+    #[test]
+    fn bundled_lookup() {
+        let db = RomDb::bundled();
+        assert_eq!(
+            RomDb::sha1_hex(&IBM_LOGO),
+            "1ba58656810b67fd131eb9af3e3987863bf26c90"
+        );
+
+        // 最初に挙がっている originalChip8 の互換動作(COSMAC VIP)になる
+        let settings = db
+            .lookup(&IBM_LOGO)
+            .expect("IBM Logo is in the bundled database");
+        assert_eq!(settings.title.as_deref(), Some("IBM Logo"));
+        assert_eq!(settings.platform_id.as_deref(), Some("originalChip8"));
+        assert_eq!(settings.platform, Some(Platform::Chip8));
+        assert_eq!(settings.tickrate, Some(15));
+        assert_eq!(
+            settings.quirks,
+            Some(Quirks {
+                display_wait: true,
+                shift_vy: true,
+                memory_increment: MemoryIncrement::Both,
+            })
+        );
+
+        // 載っていない ROM
+        assert!(db.lookup(&IBM_LOGO[..100]).is_none());
+    }
+
+    #[test]
+    fn rom_quirks_mapping() {
+        let hash = RomDb::sha1_hex(&IBM_LOGO);
+        let hashes = format!(r#"{{ "{}": 0 }}"#, hash);
+        let programs = format!(
+            r#"[{{ "title": "Test", "roms": {{ "{}": {{
+                "platforms": ["modernChip8"],
+                "quirkyPlatforms": {{ "modernChip8": {{
+                    "memoryIncrementByX": true, "jump": true, "wrap": true
+                }} }}
+            }} }} }}]"#,
+            hash
+        );
+        let db = RomDb::parse(&hashes, &programs, BUNDLED_PLATFORMS).unwrap();
+        let settings = db.lookup(&IBM_LOGO).unwrap();
+
+        // memoryIncrementByX は Fx55/Fx65 で I を x 進める
+        assert_eq!(
+            settings.quirks.map(|q| q.memory_increment),
+            Some(MemoryIncrement::ByX)
+        );
+        // wrap はこのエミュレータの動作と同じ。jump は再現できない
+        assert_eq!(settings.unsupported_quirks, vec!["jump"]);
+
+        // COSMAC VIP の wrap(描画を画面端で切る)と logic(VF のリセット)は再現できない
+        let settings = RomDb::bundled().lookup(&IBM_LOGO).unwrap();
+        assert_eq!(settings.unsupported_quirks, vec!["wrap", "logic"]);
+    }
+
+    #[test]
+    fn rom_quirks_override_platform() {
+        let hash = RomDb::sha1_hex(&IBM_LOGO);
+        let hashes = format!(r#"{{ "{}": 0 }}"#, hash);
+        let programs = format!(
+            r##"[{{ "title": "Test", "roms": {{ "{}": {{
+                "platforms": ["unknownPlatform", "modernChip8"],
+                "quirkyPlatforms": {{ "modernChip8": {{ "shift": false, "vblank": true }} }},
+                "tickrate": 30,
+                "keys": {{ "up": 5, "a": 6 }},
+                "colors": {{ "pixels": ["#000000", "#ff8000"] }}
+            }} }} }}]"##,
+            hash
+        );
+        let db = RomDb::parse(&hashes, &programs, BUNDLED_PLATFORMS).unwrap();
+        let settings = db.lookup(&IBM_LOGO).unwrap();
+
+        // 対応していないプラットフォームは飛ばす
+        assert_eq!(settings.platform_id.as_deref(), Some("modernChip8"));
+
+        // プラットフォーム定義より ROM 固有の指定を優先する
+        assert_eq!(
+            settings.quirks,
+            Some(Quirks {
+                display_wait: true,
+                shift_vy: true,
+                memory_increment: MemoryIncrement::Both,
+            })
+        );
+        assert_eq!(settings.tickrate, Some(30));
+        assert_eq!(
+            settings.keys,
+            vec![("a".to_string(), 6), ("up".to_string(), 5)]
+        );
+        assert_eq!(
+            settings.colors,
+            Some((Color::RGB(0, 0, 0), Color::RGB(255, 128, 0)))
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//
+// 実行のスケジューラ
+//
+//   実行ループ1回毎の待ち時間(wait_ms)を1スライスとして、実時間に合わせて実行するスライス数を決める。
+//   実時間に実行速度の倍率を掛けた分だけエミュレーション上の時間を進めるので、
+//   wait_ms を変えずにスロー(倍率 1 未満)や早送りができる。
+//     早送り中 : 待たずに実行し、画面の更新は実時間の1フレーム毎に間引く
+//     一時停止中: 1フレームずつ進められる(コマ送り)
+//
+
+use std::time::{Duration, Instant};
+
+/// 早送りの倍率の既定値
+pub const DEFAULT_FAST_FORWARD: f32 = 4.0;
+
+/// スローの実行速度の倍率
+pub const SLOW_SPEEDS: [f64; 4] = [0.125, 0.25, 0.5, 1.0];
+
+/// 処理が追いつかないときに、まとめて実行するエミュレーション上の時間の上限 [ms]
+const MAX_LAG_MS: f64 = 100.0;
+
+pub struct Scheduler {
+    slice_ms: f64,         // 1スライスの時間 [ms]
+    frame_us: i32,         // 1フレームの時間 [us]
+    time_ct: i32,          // フレーム境界までのダウンカウンタ [us](端数は次のフレームに持ち越す)
+    fast_forward: f64,     // 早送りの倍率
+    speed: f64,            // スローの実行速度の倍率(1.0 で等速)
+    fast: bool,            // 早送り中
+    budget_ms: f64,        // 実行できるエミュレーション上の時間 [ms]
+    paused: bool,          // 一時停止中
+    advancing: bool,       // コマ送りで1フレーム実行中
+    last: Instant,         // 前回の update() の時刻
+    last_present: Instant, // 前回画面を更新した時刻
+}
+
+impl Scheduler {
+    /// slice_ms: 1スライスの時間 [ms]、frame_us: 1フレームの時間 [us]
+    ///
+    pub fn new(slice_ms: i32, frame_us: i32, fast_forward: f32) -> Scheduler {
+        Scheduler {
+            slice_ms: slice_ms as f64,
+            frame_us,
+            time_ct: frame_us,
+            fast_forward: fast_forward as f64,
+            speed: 1.0,
+            fast: false,
+            budget_ms: 0.0,
+            paused: false,
+            advancing: false,
+            last: Instant::now(),
+            last_present: Instant::now(),
+        }
+    }
+
+    /// 現在の実行速度の倍率
+    ///
+    pub fn rate(&self) -> f64 {
+        if self.fast {
+            self.fast_forward
+        } else {
+            self.speed
+        }
+    }
+
+    /// スローの実行速度を1段階遅くする
+    ///
+    pub fn slower(&mut self) -> f64 {
+        let pos = SLOW_SPEEDS.iter().rposition(|&s| s < self.speed);
+        self.speed = pos.map_or(SLOW_SPEEDS[0], |pos| SLOW_SPEEDS[pos]);
+        self.speed
+    }
+
+    /// スローの実行速度を1段階速くする(等速まで)
+    ///
+    pub fn faster(&mut self) -> f64 {
+        let pos = SLOW_SPEEDS.iter().position(|&s| s > self.speed);
+        self.speed = pos.map_or(1.0, |pos| SLOW_SPEEDS[pos]);
+        self.speed
+    }
+
+    /// 等速に戻す
+    ///
+    pub fn reset_speed(&mut self) {
+        self.speed = 1.0;
+    }
+
+    /// 一時停止中か
+    ///
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// 一時停止を切り替える
+    ///
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advancing = false;
+    }
+
+    /// 一時停止中なら1フレームだけ実行する(実行中なら一時停止する)
+    ///
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.advancing = true;
+        } else {
+            self.paused = true;
+        }
+    }
+
+    /// 経過した実時間から、実行できる時間を求める
+    ///
+    ///   running: 実行中か(false なら時間を進めない)
+    ///   fast   : 早送りのキーを押しているか
+    ///   一時停止中はコマ送りのみ実行する
+    pub fn update(&mut self, running: bool, fast: bool) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        self.last = now;
+        self.advance(elapsed, running, fast);
+    }
+
+    /// 経過した実時間(elapsed)分だけ、実行できる時間を進める
+    ///
+    fn advance(&mut self, elapsed: Duration, running: bool, fast: bool) {
+        let paused = self.paused;
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+
+        if !running {
+            self.advancing = false;
+        }
+        self.fast = fast && running && !paused;
+        if !running || paused {
+            self.budget_ms = 0.0;
+            return;
+        }
+        let rate = self.rate();
+        self.budget_ms = (self.budget_ms + elapsed_ms * rate).min(MAX_LAG_MS * rate);
+    }
+
+    /// 次のスライスを実行するなら、そのスライスでフレーム境界を通過するか
+    ///
+    ///   実行するスライスが無くなれば None
+    pub fn next_slice(&mut self) -> Option<bool> {
+        if !self.advancing {
+            if self.budget_ms < self.slice_ms {
+                return None;
+            }
+            self.budget_ms -= self.slice_ms;
+        }
+
+        self.time_ct -= (self.slice_ms * 1000.0) as i32;
+        let frame_end = self.time_ct <= 0;
+        if frame_end {
+            // 端数を残して次のフレームに進める(平均してフレームの時間になる)
+            self.time_ct += self.frame_us;
+            self.advancing = false;
+        }
+        Some(frame_end)
+    }
+
+    /// 画面を更新するか(実時間の1フレーム毎)
+    ///
+    pub fn should_present(&mut self) -> bool {
+        let interval = Duration::from_micros(self.frame_us.max(1) as u64);
+        if self.last_present.elapsed() < interval {
+            return false;
+        }
+        self.last_present = Instant::now();
+        true
+    }
+
+    /// 次のスライスまで待つ(早送り中は待たない)
+    ///
+    pub fn wait(&self) {
+        if !self.fast {
+            std::thread::sleep(Duration::from_secs_f64(self.slice_ms / 1000.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 実行できるスライスを全て取り出す(フレーム境界を通過するか)
+    fn slices(scheduler: &mut Scheduler) -> Vec<bool> {
+        std::iter::from_fn(|| scheduler.next_slice()).collect()
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// 60Hz の1フレーム [us]
+    const FRAME_US: i32 = 1_000_000 / 60;
+
+    #[test]
+    fn budget_capped() {
+        // 長く止まっていても MAX_LAG_MS 分しかまとめて実行しない
+        let mut scheduler = Scheduler::new(2, FRAME_US, DEFAULT_FAST_FORWARD);
+        scheduler.advance(ms(1000), true, false);
+        assert_eq!(slices(&mut scheduler).len(), 50);
+
+        // 実行していない間は時間を進めない
+        scheduler.advance(ms(10), false, false);
+        assert!(slices(&mut scheduler).is_empty());
+    }
+
+    #[test]
+    fn fast_forward_rate() {
+        let mut scheduler = Scheduler::new(2, FRAME_US, 4.0);
+        scheduler.advance(ms(10), true, true);
+        assert_eq!(scheduler.rate(), 4.0);
+        assert_eq!(slices(&mut scheduler).len(), 20);
+
+        // 上限も倍率分だけ広がる
+        scheduler.advance(ms(1000), true, true);
+        assert_eq!(slices(&mut scheduler).len(), 200);
+
+        // 離せば等速に戻る
+        scheduler.advance(ms(10), true, false);
+        assert_eq!(scheduler.rate(), 1.0);
+        assert_eq!(slices(&mut scheduler).len(), 5);
+    }
+
+    #[test]
+    fn slow_motion() {
+        let mut scheduler = Scheduler::new(2, FRAME_US, DEFAULT_FAST_FORWARD);
+        assert_eq!(scheduler.slower(), 0.5);
+        assert_eq!(scheduler.slower(), 0.25);
+        assert_eq!(scheduler.slower(), 0.125);
+        assert_eq!(scheduler.slower(), 0.125);
+        assert_eq!(scheduler.faster(), 0.25);
+
+        // 16ms の実時間で 4ms 分だけ実行する
+        scheduler.advance(ms(16), true, false);
+        assert_eq!(slices(&mut scheduler).len(), 2);
+
+        scheduler.reset_speed();
+        assert_eq!(scheduler.rate(), 1.0);
+    }
+
+    #[test]
+    fn frame_rate() {
+        // 10秒分で 60Hz のフレーム境界を通過する(端数を捨てると 9スライス毎の 55.6Hz になる)
+        let mut scheduler = Scheduler::new(2, FRAME_US, DEFAULT_FAST_FORWARD);
+        let mut frames = 0;
+        for _ in 0..100 {
+            scheduler.advance(ms(100), true, false);
+            frames += slices(&mut scheduler).iter().filter(|&&end| end).count();
+        }
+        assert!((599..=600).contains(&frames), "{} frames", frames);
+
+        // PAL (50Hz)
+        let mut scheduler = Scheduler::new(2, 20_000, DEFAULT_FAST_FORWARD);
+        scheduler.advance(ms(100), true, false);
+        assert_eq!(slices(&mut scheduler).iter().filter(|&&end| end).count(), 5);
+    }
+
+    #[test]
+    fn frame_advance_while_paused() {
+        let mut scheduler = Scheduler::new(2, FRAME_US, DEFAULT_FAST_FORWARD);
+        scheduler.toggle_pause();
+        assert!(scheduler.paused());
+
+        // 一時停止中は時間が経っても実行しない。早送りもしない
+        scheduler.advance(ms(100), true, true);
+        assert_eq!(scheduler.rate(), 1.0);
+        assert!(slices(&mut scheduler).is_empty());
+
+        // コマ送りはフレーム境界までの1フレーム分だけ実行する
+        //   16.667ms を 2ms のスライスで刻むので 9スライス、端数を持ち越して次は 8スライス
+        for len in [9, 8] {
+            scheduler.advance_frame();
+            scheduler.advance(ms(100), true, false);
+            let frame = slices(&mut scheduler);
+            assert_eq!(frame.len(), len);
+            assert_eq!(frame.iter().filter(|&&end| end).count(), 1);
+            assert_eq!(frame.last(), Some(&true));
+            assert!(slices(&mut scheduler).is_empty());
+        }
+
+        // 実行中のコマ送りは一時停止する
+        scheduler.toggle_pause();
+        assert!(!scheduler.paused());
+        scheduler.advance_frame();
+        assert!(scheduler.paused());
+    }
+}
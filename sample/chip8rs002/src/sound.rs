@@ -0,0 +1,80 @@
+//
+// デジタルサンプル(8bit符号無しPCM)の再生
+//
+//   SDL2 のオーディオコールバックで、サンプルを出力周波数に合わせて間引き/水増しして再生する。
+//
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use crate::machine::SoundEvent;
+
+pub struct SamplePlayer {
+    pub data: Vec<u8>, // 8bit符号無しPCM
+    pub pos: f64,      // 再生位置
+    pub step: f64,     // 出力1サンプル毎に進める量(サンプル周波数 / 出力周波数)
+    pub looping: bool, // 最後まで再生したら先頭に戻る
+    pub out_freq: f64, // 出力周波数
+}
+
+impl AudioCallback for SamplePlayer {
+    type Channel = u8;
+
+    fn callback(&mut self, out: &mut [u8]) {
+        for dst in out.iter_mut() {
+            let mut pos = self.pos as usize;
+            if pos >= self.data.len() && self.looping && !self.data.is_empty() {
+                self.pos -= self.data.len() as f64;
+                pos = self.pos as usize;
+            }
+
+            // 無音は 0x80
+            *dst = self.data.get(pos).copied().unwrap_or(0x80);
+            self.pos += self.step;
+        }
+    }
+}
+
+impl SamplePlayer {
+    /// 再生用のオーディオデバイスを開く
+    ///
+    pub fn open(audio: &AudioSubsystem) -> Result<AudioDevice<SamplePlayer>, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: Some(512),
+        };
+
+        let device = audio.open_playback(None, &desired, |spec| SamplePlayer {
+            data: Vec::new(),
+            pos: 0.0,
+            step: 0.0,
+            looping: false,
+            out_freq: spec.freq as f64,
+        })?;
+        device.resume();
+
+        Ok(device)
+    }
+
+    /// 実行エンジンからの再生要求を処理
+    ///
+    pub fn handle(&mut self, event: SoundEvent) {
+        match event {
+            SoundEvent::Play {
+                data,
+                rate,
+                looping,
+            } => {
+                self.data = data;
+                self.pos = 0.0;
+                self.step = rate as f64 / self.out_freq;
+                self.looping = looping;
+            }
+            SoundEvent::Stop => {
+                self.data.clear();
+                self.looping = false;
+            }
+        }
+    }
+}
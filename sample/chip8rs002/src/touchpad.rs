@@ -0,0 +1,219 @@
+//
+// 画面上のキーパッド(マウス、タッチパネル用)
+//
+//   COSMAC VIP 風の 4x4 のキーパッドを、画面の横(beside)または画面の上(over)に描画し、
+//   マウスのクリックやタッチでキーを押せるようにする。
+//   キーの表示には 16進数字フォント(Chip8::FONT)を使う。
+//
+
+use sdl2::event::Event;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::chip8::Chip8;
+
+/// マウスのイベントのうち、タッチから作られたもの(SDL_TOUCH_MOUSEID)
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
+
+/// キーパッドの配置
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// 画面の右横に並べる(ウインドウを広げる)
+    Beside,
+    /// 画面の右側に半透明で重ねる
+    Over,
+}
+
+impl Placement {
+    /// 配置名一覧
+    pub const NAMES: [&'static str; 3] = ["off", "beside", "over"];
+
+    /// 配置名から取得("off" は None)
+    ///
+    pub fn from_name(name: &str) -> Result<Option<Placement>, String> {
+        match name {
+            "off" => Ok(None),
+            "beside" => Ok(Some(Placement::Beside)),
+            "over" => Ok(Some(Placement::Over)),
+            _ => Err(format!(
+                "Unknown touch keypad placement: '{}' (available: {})",
+                name,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+pub struct TouchKeypad {
+    pub placement: Placement,               // 配置
+    pub layout: [u8; 16],                   // 4x4 の位置に対応するキー値
+    pub mouse: Option<usize>,               // マウスで押している位置
+    pub fingers: Vec<(i64, Option<usize>)>, // タッチ中の指と押している位置
+}
+
+impl TouchKeypad {
+    const KEY_COLOR: Color = Color::RGBA(64, 64, 64, 160); // キーの色
+    const PRESSED_COLOR: Color = Color::RGBA(160, 160, 160, 200); // 押されているキーの色
+    const LABEL_COLOR: Color = Color::RGBA(255, 255, 255, 255); // キーの文字の色
+
+    /// layout: 4x4 の位置に対応するキー値(プラットフォームで決まる)
+    ///
+    pub fn new(placement: Placement, layout: [u8; 16]) -> TouchKeypad {
+        TouchKeypad {
+            placement,
+            layout,
+            mouse: None,
+            fingers: Vec::new(),
+        }
+    }
+
+    /// ウインドウの大きさ (横, 縦)
+    ///   画面の大きさが (width, height) のとき、横に並べるならキーパッドの分だけ広げる
+    pub fn window_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.placement {
+            Placement::Beside => (width + height, height),
+            Placement::Over => (width, height),
+        }
+    }
+
+    /// 画面を描画する領域
+    ///
+    pub fn display_rect(&self, win_w: u32, win_h: u32) -> Rect {
+        match self.placement {
+            Placement::Beside => Rect::new(0, 0, win_w.saturating_sub(win_h).max(1), win_h),
+            Placement::Over => Rect::new(0, 0, win_w, win_h),
+        }
+    }
+
+    /// キーパッドを描画する領域(正方形)
+    ///
+    pub fn keypad_rect(&self, win_w: u32, win_h: u32) -> Rect {
+        let size = win_h.min(win_w);
+        Rect::new((win_w - size) as i32, 0, size, size)
+    }
+
+    /// (x, y) にあるキーの位置(0〜15)
+    ///
+    fn hit(&self, x: i32, y: i32, win_w: u32, win_h: u32) -> Option<usize> {
+        let rect = self.keypad_rect(win_w, win_h);
+        if !rect.contains_point((x, y)) {
+            return None;
+        }
+        let col = ((x - rect.x()) as u32 * 4 / rect.width()).min(3) as usize;
+        let row = ((y - rect.y()) as u32 * 4 / rect.height()).min(3) as usize;
+        Some(row * 4 + col)
+    }
+
+    /// マウス、タッチのイベントを処理する
+    ///
+    pub fn handle_event(&mut self, event: &Event, win_w: u32, win_h: u32) {
+        // タッチの座標は 0.0〜1.0
+        let touch = |x: f32, y: f32| ((x * win_w as f32) as i32, (y * win_h as f32) as i32);
+
+        match *event {
+            Event::MouseButtonDown {
+                which,
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } if which != TOUCH_MOUSE_ID => self.mouse = self.hit(x, y, win_w, win_h),
+            Event::MouseMotion {
+                which,
+                mousestate,
+                x,
+                y,
+                ..
+            } if which != TOUCH_MOUSE_ID && mousestate.left() => {
+                self.mouse = self.hit(x, y, win_w, win_h)
+            }
+            Event::MouseButtonUp {
+                which,
+                mouse_btn: MouseButton::Left,
+                ..
+            } if which != TOUCH_MOUSE_ID => self.mouse = None,
+            Event::FingerDown {
+                finger_id, x, y, ..
+            }
+            | Event::FingerMotion {
+                finger_id, x, y, ..
+            } => {
+                let (x, y) = touch(x, y);
+                let pos = self.hit(x, y, win_w, win_h);
+                self.fingers.retain(|&(id, _)| id != finger_id);
+                self.fingers.push((finger_id, pos));
+            }
+            Event::FingerUp { finger_id, .. } => {
+                self.fingers.retain(|&(id, _)| id != finger_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// 押されている位置か
+    ///
+    fn is_pressed(&self, pos: usize) -> bool {
+        self.mouse == Some(pos) || self.fingers.iter().any(|&(_, p)| p == Some(pos))
+    }
+
+    /// 押されているキーのキー値をキー状態に反映する
+    ///
+    pub fn update(&self, key_status: &mut [u8; 16]) {
+        for (pos, &key) in self.layout.iter().enumerate() {
+            if self.is_pressed(pos) {
+                key_status[(key & 0xF) as usize] = key & 0xF;
+            }
+        }
+    }
+
+    /// キーパッドを描画する
+    ///
+    pub fn draw(&self, canvas: &mut Canvas<Window>) {
+        let (win_w, win_h) = canvas.output_size().unwrap_or((0, 0));
+        let rect = self.keypad_rect(win_w, win_h);
+        let cell = rect.width() / 4;
+        let margin = cell / 16;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        for (pos, &key) in self.layout.iter().enumerate() {
+            let x = rect.x() + (pos % 4) as i32 * cell as i32;
+            let y = rect.y() + (pos / 4) as i32 * cell as i32;
+
+            // キー
+            canvas.set_draw_color(if self.is_pressed(pos) {
+                Self::PRESSED_COLOR
+            } else {
+                Self::KEY_COLOR
+            });
+            let _ = canvas.fill_rect(Rect::new(
+                x + margin as i32,
+                y + margin as i32,
+                cell - margin * 2,
+                cell - margin * 2,
+            ));
+
+            // キーの文字(4x5 のフォントを中央に)
+            let dot = (cell / 10).max(1);
+            let lx = x + ((cell - dot * 4) / 2) as i32;
+            let ly = y + ((cell - dot * 5) / 2) as i32;
+            canvas.set_draw_color(Self::LABEL_COLOR);
+            for row in 0..5 {
+                let bits = Chip8::FONT[(key & 0xF) as usize * 5 + row];
+                for col in 0..4 {
+                    if bits & (0x80 >> col) != 0 {
+                        let _ = canvas.fill_rect(Rect::new(
+                            lx + (col as u32 * dot) as i32,
+                            ly + (row as u32 * dot) as i32,
+                            dot,
+                            dot,
+                        ));
+                    }
+                }
+            }
+        }
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
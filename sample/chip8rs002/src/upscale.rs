@@ -0,0 +1,373 @@
+//
+// ドット絵向けの拡大アルゴリズム
+//
+//   表示内容(RGB24)を画素の並びを見ながら拡大し、斜めの線をなめらかにする。
+//     scale2x: EPX/Scale2x (2倍)
+//     scale3x: AdvMAME3x/Scale3x (3倍)
+//     eagle  : Eagle (2倍)
+//     xbr    : xBR の簡易版(2倍、近傍 3x3 の輪郭の向きで角を混ぜる)
+//   画面表示のほか、スクリーンショットや動画の書き出しでも使う。
+//
+
+/// 拡大方法
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Upscaler {
+    None,
+    Scale2x,
+    Scale3x,
+    Eagle,
+    Xbr,
+}
+
+type Pixel = [u8; 3];
+
+impl Upscaler {
+    /// 拡大方法の名前一覧
+    pub const NAMES: [&'static str; 5] = ["none", "scale2x", "scale3x", "eagle", "xbr"];
+
+    /// 名前から取得
+    ///
+    pub fn from_name(name: &str) -> Option<Upscaler> {
+        match name {
+            "none" => Some(Upscaler::None),
+            "scale2x" => Some(Upscaler::Scale2x),
+            "scale3x" => Some(Upscaler::Scale3x),
+            "eagle" => Some(Upscaler::Eagle),
+            "xbr" => Some(Upscaler::Xbr),
+            _ => None,
+        }
+    }
+
+    /// 名前
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            Upscaler::None => "none",
+            Upscaler::Scale2x => "scale2x",
+            Upscaler::Scale3x => "scale3x",
+            Upscaler::Eagle => "eagle",
+            Upscaler::Xbr => "xbr",
+        }
+    }
+
+    /// 次の拡大方法(実行中の切り替え用)
+    ///
+    pub fn next(&self) -> Upscaler {
+        match self {
+            Upscaler::None => Upscaler::Scale2x,
+            Upscaler::Scale2x => Upscaler::Scale3x,
+            Upscaler::Scale3x => Upscaler::Eagle,
+            Upscaler::Eagle => Upscaler::Xbr,
+            Upscaler::Xbr => Upscaler::None,
+        }
+    }
+
+    /// 拡大率
+    ///
+    pub fn factor(&self) -> usize {
+        match self {
+            Upscaler::None => 1,
+            Upscaler::Scale3x => 3,
+            Upscaler::Scale2x | Upscaler::Eagle | Upscaler::Xbr => 2,
+        }
+    }
+
+    /// src (RGB24, size) を拡大した画像 (RGB24, 拡大後の大きさ)
+    ///
+    pub fn apply(&self, src: &[u8], size: (usize, usize)) -> (Vec<u8>, (usize, usize)) {
+        let (width, height) = size;
+        let factor = self.factor();
+        if *self == Upscaler::None || width == 0 || height == 0 {
+            return (src.to_vec(), size);
+        }
+
+        let out_w = width * factor;
+        let mut out = vec![0; out_w * height * factor * 3];
+
+        // 画面の外は端の画素を延ばす
+        let px = |x: isize, y: isize| -> Pixel {
+            let x = x.clamp(0, width as isize - 1) as usize;
+            let y = y.clamp(0, height as isize - 1) as usize;
+            let i = (y * width + x) * 3;
+            [src[i], src[i + 1], src[i + 2]]
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                // 近傍 3x3 (n[dy + 1][dx + 1])
+                let (xi, yi) = (x as isize, y as isize);
+                let n: [[Pixel; 3]; 3] =
+                    [-1, 0, 1].map(|dy| [-1, 0, 1].map(|dx| px(xi + dx, yi + dy)));
+
+                let block: Vec<Pixel> = match self {
+                    Upscaler::None => unreachable!(),
+                    Upscaler::Scale2x => scale2x(&n).to_vec(),
+                    Upscaler::Scale3x => scale3x(&n).to_vec(),
+                    Upscaler::Eagle => eagle(&n).to_vec(),
+                    Upscaler::Xbr => xbr(&n).to_vec(),
+                };
+
+                for (i, p) in block.iter().enumerate() {
+                    let (ox, oy) = (x * factor + i % factor, y * factor + i / factor);
+                    let o = (oy * out_w + ox) * 3;
+                    out[o..o + 3].copy_from_slice(p);
+                }
+            }
+        }
+
+        (out, (out_w, height * factor))
+    }
+}
+
+/// EPX/Scale2x
+///
+///   | . A . |      | E0 E1 |
+///   | C P B |  ->  | E2 E3 |
+///   | . D . |
+fn scale2x(n: &[[Pixel; 3]; 3]) -> [Pixel; 4] {
+    let (a, b, c, d, p) = (n[0][1], n[1][2], n[1][0], n[2][1], n[1][1]);
+    [
+        if c == a && c != d && a != b { a } else { p },
+        if a == b && a != c && b != d { b } else { p },
+        if d == c && d != b && c != a { c } else { p },
+        if b == d && b != a && d != c { d } else { p },
+    ]
+}
+
+/// AdvMAME3x/Scale3x
+///
+///   | A B C |      | E0 E1 E2 |
+///   | D E F |  ->  | E3 E4 E5 |
+///   | G H I |      | E6 E7 E8 |
+fn scale3x(n: &[[Pixel; 3]; 3]) -> [Pixel; 9] {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *n;
+    if b == h || d == f {
+        return [e; 9];
+    }
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        },
+        e,
+        if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        },
+        if h == f { f } else { e },
+    ]
+}
+
+/// Eagle
+///
+///   角の 3 画素が同じ色なら、その色で角を埋める
+fn eagle(n: &[[Pixel; 3]; 3]) -> [Pixel; 4] {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *n;
+    [
+        if a == b && a == d { a } else { e },
+        if c == b && c == f { c } else { e },
+        if g == d && g == h { g } else { e },
+        if i == f && i == h { i } else { e },
+    ]
+}
+
+/// xBR の簡易版
+///
+///   角毎に、角を横切る輪郭(例えば右下なら H-F)と中心を通る輪郭(E-I)の強さを比べ、
+///   角を横切る輪郭の方がはっきりしていれば、角を近い方の色と混ぜる
+fn xbr(n: &[[Pixel; 3]; 3]) -> [Pixel; 4] {
+    let at = |dx: isize, dy: isize| n[(dy + 1) as usize][(dx + 1) as usize];
+    let e = at(0, 0);
+
+    // 左上、右上、左下、右下の順
+    [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(sx, sy)| {
+        let h = at(0, sy); // 角の縦の隣
+        let f = at(sx, 0); // 角の横の隣
+        let i = at(sx, sy); // 角の斜め
+        let c = at(sx, -sy);
+        let g = at(-sx, sy);
+        let b = at(0, -sy);
+        let d = at(-sx, 0);
+
+        let across = distance(e, c) + distance(e, g) + 4 * distance(h, f);
+        let through = distance(f, b) + distance(h, d) + 4 * distance(e, i);
+        if across < through && e != h && e != f {
+            let near = if distance(e, f) <= distance(e, h) {
+                f
+            } else {
+                h
+            };
+            mix(e, near)
+        } else {
+            e
+        }
+    })
+}
+
+/// 色の差(輝度を重くした YUV の距離)
+///
+fn distance(a: Pixel, b: Pixel) -> u32 {
+    let yuv = |p: Pixel| {
+        let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+        (
+            (299 * r + 587 * g + 114 * b) / 1000,
+            (-169 * r - 331 * g + 500 * b) / 1000,
+            (500 * r - 419 * g - 81 * b) / 1000,
+        )
+    };
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    (48 * (ya - yb).unsigned_abs() + 7 * (ua - ub).unsigned_abs() + 6 * (va - vb).unsigned_abs())
+        / 8
+}
+
+/// 2 色を半分ずつ混ぜる
+///
+fn mix(a: Pixel, b: Pixel) -> Pixel {
+    [0, 1, 2].map(|c| ((a[c] as u16 + b[c] as u16) / 2) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 文字列の画像を RGB24 にする('#' 白、'.' 黒、'm' 白と黒を混ぜた色)
+    fn image(rows: &[&str]) -> (Vec<u8>, (usize, usize)) {
+        let rgb = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .flat_map(|ch| match ch {
+                '#' => [255; 3],
+                'm' => [127; 3],
+                _ => [0; 3],
+            })
+            .collect();
+        (rgb, (rows[0].len(), rows.len()))
+    }
+
+    // 3x3 の斜めの線
+    const DIAGONAL: [&str; 3] = [
+        "#..", //
+        ".#.", //
+        "..#",
+    ];
+
+    // 3x3 の L 字
+    const L_SHAPE: [&str; 3] = [
+        "#..", //
+        "#..", //
+        "###",
+    ];
+
+    fn check(upscaler: Upscaler, src: &[&str], expected: &[&str]) {
+        let (rgb, size) = image(src);
+        assert_eq!(
+            upscaler.apply(&rgb, size),
+            image(expected),
+            "{:?}",
+            upscaler
+        );
+    }
+
+    #[test]
+    fn none() {
+        check(Upscaler::None, &DIAGONAL, &DIAGONAL);
+    }
+
+    #[test]
+    fn scale2x() {
+        // 端の画素は画面の外に延びているものとして扱うので、角は欠けない
+        check(
+            Upscaler::Scale2x,
+            &DIAGONAL,
+            &[
+                "##....", //
+                "#.#...", //
+                ".###..", //
+                "..###.", //
+                "...#.#", //
+                "....##",
+            ],
+        );
+        check(
+            Upscaler::Scale2x,
+            &L_SHAPE,
+            &[
+                "##....", //
+                "##....", //
+                "##....", //
+                "###...", //
+                "######", //
+                "######",
+            ],
+        );
+    }
+
+    #[test]
+    fn scale3x() {
+        // 内側の角だけを丸める
+        check(
+            Upscaler::Scale3x,
+            &L_SHAPE,
+            &[
+                "###......",
+                "###......",
+                "###......",
+                "###......",
+                "####.....",
+                "#####....",
+                "#########",
+                "#########",
+                "#########",
+            ],
+        );
+    }
+
+    #[test]
+    fn eagle() {
+        check(
+            Upscaler::Eagle,
+            &DIAGONAL,
+            &[
+                "##....", //
+                "##....", //
+                "..#...", //
+                "...#..", //
+                "....##", //
+                "....##",
+            ],
+        );
+    }
+
+    #[test]
+    fn xbr() {
+        // 斜めの線の両側を中間の色で埋める
+        check(
+            Upscaler::Xbr,
+            &DIAGONAL,
+            &[
+                "##....", //
+                "##m...", //
+                ".m#m..", //
+                "..m#m.", //
+                "...m##", //
+                "....##",
+            ],
+        );
+    }
+}
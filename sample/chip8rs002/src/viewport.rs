@@ -0,0 +1,98 @@
+//
+// 画面の拡大方法
+//
+//   ウインドウ(またはフルスクリーン)の中に、画面をどの大きさで置くかを決める。
+//     integer: 整数倍に拡大し、余白は黒で埋める(ドットの大きさが揃う)
+//     fit    : 縦横比を保ったまま最大に拡大し、余白は黒で埋める
+//     stretch: 縦横比を無視して全体に広げる
+//   縦横比の補正(aspect correction)を有効にすると、実機のテレビと同じ 4:3 で表示する。
+//
+
+use sdl2::rect::Rect;
+
+/// 拡大方法
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleMode {
+    Integer,
+    Fit,
+    Stretch,
+}
+
+impl ScaleMode {
+    /// 拡大方法の名前一覧
+    pub const NAMES: [&'static str; 3] = ["integer", "fit", "stretch"];
+
+    /// 名前から取得
+    ///
+    pub fn from_name(name: &str) -> Option<ScaleMode> {
+        match name {
+            "integer" => Some(ScaleMode::Integer),
+            "fit" => Some(ScaleMode::Fit),
+            "stretch" => Some(ScaleMode::Stretch),
+            _ => None,
+        }
+    }
+
+    /// 名前
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Fit => "fit",
+            ScaleMode::Stretch => "stretch",
+        }
+    }
+
+    /// 次の拡大方法(実行中の切り替え用)
+    ///
+    pub fn next(&self) -> ScaleMode {
+        match self {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Stretch,
+            ScaleMode::Stretch => ScaleMode::Integer,
+        }
+    }
+}
+
+/// 縦横比の補正をしたときの表示比率 (横, 縦)
+pub const CORRECTED_ASPECT: (u32, u32) = (4, 3);
+
+/// area の中で画面を表示する領域
+///
+///   display: 画面の表示上のサイズ (横, 縦)
+///   aspect : 縦横比を補正するなら true
+pub fn display_rect(mode: ScaleMode, display: (usize, usize), aspect: bool, area: Rect) -> Rect {
+    let (disp_w, disp_h) = (display.0 as u32, display.1 as u32);
+    let (ratio_w, ratio_h) = if aspect {
+        CORRECTED_ASPECT
+    } else {
+        (disp_w, disp_h)
+    };
+
+    let (w, h) = match mode {
+        ScaleMode::Stretch => return area,
+        ScaleMode::Fit => {
+            if area.width() * ratio_h <= area.height() * ratio_w {
+                (area.width(), area.width() * ratio_h / ratio_w)
+            } else {
+                (area.height() * ratio_w / ratio_h, area.height())
+            }
+        }
+        ScaleMode::Integer => {
+            // 横方向の整数倍を基準にし、縦は表示比率に合わせる
+            let scale_w = area.width() / disp_w;
+            let scale_h = area.height() * ratio_w / (ratio_h * disp_w);
+            let scale = scale_w.min(scale_h).max(1);
+            (disp_w * scale, disp_w * scale * ratio_h / ratio_w)
+        }
+    };
+
+    // 中央に置く
+    let (w, h) = (w.max(1), h.max(1));
+    Rect::new(
+        area.x() + (area.width() as i32 - w as i32) / 2,
+        area.y() + (area.height() as i32 - h as i32) / 2,
+        w,
+        h,
+    )
+}
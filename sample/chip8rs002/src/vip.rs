@@ -0,0 +1,393 @@
+//
+// COSMAC VIP エミュレーション
+//
+//   CDP1802 CPU、CDP1861(Pixie)ビデオチップ、16キーの16進キーパッドを持つ
+//   RCA COSMAC VIP を再現し、オリジナルの CHIP-8 インタプリタ(機械語)を実行する。
+//   CHIP-8 命令はインタプリタが解釈するため、0nnn(SYS)で 1802 の機械語を呼び出す
+//   ハイブリッドROMもそのまま動作する。
+//
+//   メモリマップ
+//     0000 - 0FFF  RAM 4KB (0x7FFF までミラー)
+//     8000 - 81FF  モニタROM 512byte (0xFFFF までミラー)
+//   リセット直後は、A15 が 1 になるまでモニタROMが 0000番地にも見える。
+//
+
+use std::fs;
+use std::path::Path;
+
+use sdl2::pixels::Color;
+
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::machine::{Machine, Reset};
+use crate::palette::Palette;
+
+/// VIP のメモリ、I/O
+pub struct VipBus {
+    pub ram: [u8; Vip::RAM_SIZE],
+    pub rom: [u8; Vip::ROM_SIZE],
+    pub rom_at_zero: bool, // リセット直後はモニタROMが 0000番地に見える
+
+    pub display_on: bool, // 1861 表示有効(INP 1 で ON、OUT 1 で OFF)
+    pub ef1: bool,        // 1861 表示ステータス(表示期間の前後4ライン)
+    pub key_latch: u8,    // OUT 2 で選択したキー番号
+    pub keys: [bool; 16], // 押下中のキー
+}
+
+impl Bus for VipBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr & 0x8000 != 0 {
+            // A15 が 1 になったら RAM を 0000番地に戻す
+            self.rom_at_zero = false;
+            self.rom[addr as usize % Vip::ROM_SIZE]
+        } else if self.rom_at_zero {
+            self.rom[addr as usize % Vip::ROM_SIZE]
+        } else {
+            self.ram[addr as usize % Vip::RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if addr & 0x8000 == 0 {
+            self.ram[addr as usize % Vip::RAM_SIZE] = val;
+        }
+    }
+
+    fn output(&mut self, port: u8, val: u8) {
+        match port {
+            1 => self.display_on = false,    // OUT 1 - 表示 OFF
+            2 => self.key_latch = val & 0xF, // OUT 2 - キーラッチ
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            // INP 1 - 表示 ON
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn ef(&self, n: u8) -> bool {
+        match n {
+            1 => self.ef1,                           // 1861 表示ステータス
+            3 => self.keys[self.key_latch as usize], // キーパッド
+            _ => false,                              // EF2: カセット、EF4: 未使用
+        }
+    }
+}
+
+pub struct Vip {
+    pub cpu: Cdp1802,
+    pub bus: VipBus,
+
+    pub frame_cycle: u32,                       // フレーム内のマシンサイクル位置
+    pub dma_line: Option<u32>,                  // 直前に DMA したライン
+    pub int_done: bool,                         // このフレームで割り込みを要求済みか
+    pub cycles_per_step: u32,                   // step() 1回で実行するマシンサイクル数
+    pub display: [u8; Vip::XSIZE * Vip::YSIZE], // 1861 の表示内容
+    pub palette: Palette,                       // 表示色
+    pub interpreter: Vec<u8>, // CHIP-8インタプリタのイメージ(ハードリセットで読み込み直す)
+    pub rom: Vec<u8>,         // CHIP-8 プログラムのイメージ(ハードリセットで読み込み直す)
+}
+
+impl Vip {
+    pub const RAM_SIZE: usize = 0x1000; // RAM 4KB
+    pub const ROM_SIZE: usize = 0x200; // モニタROM 512byte
+
+    pub const XSIZE: usize = 64; // 横64 ピクセル
+    pub const YSIZE: usize = 128; // 縦128 ライン
+
+    // CDP1861 のタイミング(1.76MHz / 8 = 220KHz のマシンサイクル単位)
+    pub const CYCLES_PER_LINE: u32 = 14; // 1ライン 14マシンサイクル
+    pub const LINES_PER_FRAME: u32 = 262; // 1フレーム 262ライン(約60Hz)
+    pub const CYCLES_PER_FRAME: u32 = Self::CYCLES_PER_LINE * Self::LINES_PER_FRAME;
+    pub const DISPLAY_START: u32 = 80; // 表示開始ライン
+    pub const DISPLAY_END: u32 = Self::DISPLAY_START + Self::YSIZE as u32;
+    pub const INT_CYCLE: u32 = Self::DISPLAY_START * Self::CYCLES_PER_LINE - 29; // 割り込みは最初の DMA の29サイクル前
+
+    /// モニタROMとCHIP-8インタプリタのイメージから VIP を作成
+    ///
+    ///   step_ms: step() 1回分の実時間(ミリ秒)
+    pub fn new<P: AsRef<Path>>(monitor: P, interpreter: P, step_ms: u32) -> Result<Vip, String> {
+        let mut bus = VipBus {
+            ram: [0_u8; Vip::RAM_SIZE],
+            rom: [0_u8; Vip::ROM_SIZE],
+            rom_at_zero: true,
+            display_on: false,
+            ef1: false,
+            key_latch: 0,
+            keys: [false; 16],
+        };
+
+        // モニタROM(8000番地〜)
+        let monitor = fs::read(monitor).map_err(|e| format!("VIP monitor ROM: {}", e))?;
+        let size = monitor.len().min(Vip::ROM_SIZE);
+        bus.rom[..size].copy_from_slice(&monitor[..size]);
+
+        // CHIP-8インタプリタ(0000番地〜)
+        let interpreter =
+            fs::read(interpreter).map_err(|e| format!("VIP CHIP-8 interpreter: {}", e))?;
+        let size = interpreter.len().min(0x200);
+        bus.ram[..size].copy_from_slice(&interpreter[..size]);
+
+        Ok(Vip {
+            cpu: Cdp1802::new(),
+            bus,
+            frame_cycle: 0,
+            dma_line: None,
+            int_done: false,
+            cycles_per_step: Self::CYCLES_PER_FRAME * 60 * step_ms / 1000,
+            display: [0_u8; Vip::XSIZE * Vip::YSIZE],
+            palette: Palette::default(),
+            interpreter,
+            rom: Vec::new(),
+        })
+    }
+
+    /// CHIP-8 プログラムを 0200番地に配置する
+    ///
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        let size = rom.len().min(Vip::RAM_SIZE - 0x200);
+        self.bus.ram[0x200..(size + 0x200)].copy_from_slice(&rom[..size]);
+        self.rom = rom;
+    }
+
+    /// 指定マシンサイクル数だけ実行する
+    ///
+    pub fn run_cycles(&mut self, cycles: u32) {
+        let mut count = 0;
+        while count < cycles {
+            count += self.run_slot();
+        }
+    }
+
+    /// CPU命令、DMA、割り込みのうち次に起こるものを1つ処理し、消費サイクル数を返す
+    ///
+    fn run_slot(&mut self) -> u32 {
+        let line = self.frame_cycle / Self::CYCLES_PER_LINE;
+
+        // EF1 は表示期間の直前4ラインと最後の4ラインでアクティブ
+        self.bus.ef1 = self.bus.display_on
+            && ((Self::DISPLAY_START - 4..Self::DISPLAY_START).contains(&line)
+                || (Self::DISPLAY_END - 4..Self::DISPLAY_END).contains(&line));
+
+        let cycles = if self.bus.display_on
+            && (Self::DISPLAY_START..Self::DISPLAY_END).contains(&line)
+            && self.dma_line != Some(line)
+        {
+            // 表示ライン: 8バイト(64ピクセル)を DMA で読み出す
+            // (実行中の命令が終わってから DMA が入る)
+            self.dma_line = Some(line);
+            let row = (line - Self::DISPLAY_START) as usize * Self::XSIZE;
+            for byte in 0..8 {
+                let val = self.cpu.dma_out(&mut self.bus);
+                for bit in 0..8 {
+                    self.display[row + byte * 8 + bit] = (val >> (7 - bit)) & 0x1;
+                }
+            }
+            8
+        } else if self.bus.display_on
+            && !self.int_done
+            && (Self::INT_CYCLE..Self::DISPLAY_START * Self::CYCLES_PER_LINE)
+                .contains(&self.frame_cycle)
+            && self.cpu.ie
+        {
+            // 表示開始前の割り込み
+            self.int_done = true;
+            self.cpu.interrupt();
+            1
+        } else {
+            self.cpu.step(&mut self.bus)
+        };
+
+        // フレームの終わり
+        self.frame_cycle += cycles;
+        if self.frame_cycle >= Self::CYCLES_PER_FRAME {
+            self.frame_cycle -= Self::CYCLES_PER_FRAME;
+            self.dma_line = None;
+            self.int_done = false;
+
+            // 表示 OFF の間は何も映らない
+            if !self.bus.display_on {
+                self.display.fill(0);
+            }
+        }
+
+        cycles
+    }
+}
+
+impl Machine for Vip {
+    fn step(&mut self, keys: &[u8; 16], _keys2: &[u8; 16]) -> Result<(), String> {
+        // キーパッドの状態を更新
+        for (key, pressed) in self.bus.keys.iter_mut().enumerate() {
+            *pressed = keys.contains(&(key as u8));
+        }
+
+        self.run_cycles(self.cycles_per_step);
+        Ok(())
+    }
+
+    fn tick_frame(&mut self) {
+        // フレームのタイミングは 1861 が作るので何もしない
+    }
+
+    fn frame(&self) -> (usize, usize, &[u8]) {
+        (Self::XSIZE, Self::YSIZE, &self.display)
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        // 1861 は横長の画素(64x128)なので、64x32 相当の比率で表示する
+        (Self::XSIZE, Self::YSIZE / 4)
+    }
+
+    fn pixel_color(&self, _x: usize, _y: usize, pixel: u8) -> Color {
+        self.palette.color(pixel)
+    }
+
+    fn reset(&mut self, kind: Reset) {
+        // リセット直後はモニタROMから実行する(RAM はそのまま)
+        self.cpu = Cdp1802::new();
+        self.bus.rom_at_zero = true;
+        self.bus.display_on = false;
+        self.bus.ef1 = false;
+        self.bus.key_latch = 0;
+        self.frame_cycle = 0;
+        self.dma_line = None;
+        self.int_done = false;
+        self.display = [0_u8; Vip::XSIZE * Vip::YSIZE];
+
+        if kind == Reset::Hard {
+            // RAM を消して、インタプリタと CHIP-8 プログラムを読み込み直す
+            self.bus.ram = [0_u8; Vip::RAM_SIZE];
+            let size = self.interpreter.len().min(0x200);
+            self.bus.ram[..size].copy_from_slice(&self.interpreter[..size]);
+            let size = self.rom.len().min(Vip::RAM_SIZE - 0x200);
+            self.bus.ram[0x200..(size + 0x200)].copy_from_slice(&self.rom[..size]);
+        }
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = palette.clone();
+    }
+
+    fn report(&self) {
+        let cpu = &self.cpu;
+        println!(
+            "[R{:X}:{:04x}] D:{:02x} DF:{} X:{:X} P:{:X} T:{:02x} IE:{} Q:{}",
+            cpu.p,
+            cpu.r[cpu.p as usize],
+            cpu.d,
+            cpu.df,
+            cpu.x,
+            cpu.p,
+            cpu.t,
+            cpu.ie as u8,
+            cpu.q as u8
+        );
+        for i in 0..16 {
+            print!(" R{:X}:{:04x}", i, cpu.r[i]);
+            if i == 7 {
+                println!();
+            }
+        }
+        println!();
+    }
+
+    fn error_mes(&self, mes: &str) -> String {
+        format!(
+            "{} [R{:X}:{:04x}]",
+            mes, self.cpu.p, self.cpu.r[self.cpu.p as usize]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RAM に機械語プログラムを置き、表示 ON の状態で 0000番地から実行する VIP
+    fn vip_with_program(program: &[u8]) -> Vip {
+        let mut bus = VipBus {
+            ram: [0_u8; Vip::RAM_SIZE],
+            rom: [0_u8; Vip::ROM_SIZE],
+            rom_at_zero: false,
+            display_on: true,
+            ef1: false,
+            key_latch: 0,
+            keys: [false; 16],
+        };
+        bus.ram[..program.len()].copy_from_slice(program);
+
+        let mut cpu = Cdp1802::new();
+        cpu.ie = false; // 割り込みなし
+        cpu.r[0] = 0x0800; // DMA は空き領域から
+        cpu.x = 2;
+        cpu.p = 3;
+        cpu.r[3] = 0x0000;
+
+        Vip {
+            cpu,
+            bus,
+            frame_cycle: 0,
+            dma_line: None,
+            int_done: false,
+            cycles_per_step: Vip::CYCLES_PER_FRAME,
+            display: [0_u8; Vip::XSIZE * Vip::YSIZE],
+            palette: Palette::default(),
+            interpreter: Vec::new(),
+            rom: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn ef1_active_lines() {
+        // 30 00 - BR 0000 (空回り)
+        let mut vip = vip_with_program(&[0x30, 0x00]);
+
+        // EF1 は表示期間の直前4ラインと最後の4ラインでアクティブ
+        let mut active = [false; Vip::LINES_PER_FRAME as usize];
+        while vip.frame_cycle + 2 < Vip::CYCLES_PER_FRAME {
+            let line = vip.frame_cycle / Vip::CYCLES_PER_LINE;
+            vip.run_slot();
+            active[line as usize] |= vip.bus.ef1;
+        }
+        let lines: Vec<u32> = (0..Vip::LINES_PER_FRAME)
+            .filter(|&line| active[line as usize])
+            .collect();
+        let expected: Vec<u32> = (76..80).chain(204..208).collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn ef1_seen_by_cpu() {
+        // 00: 3C 00  BN1 0000  EF1 を待つ
+        // 02: 7B     SEQ
+        // 03: 34 03  B1 0003   EF1 が消えるのを待つ
+        // 05: 7A     REQ
+        // 06: 30 06  BR 0006
+        let mut vip = vip_with_program(&[0x3C, 0x00, 0x7B, 0x34, 0x03, 0x7A, 0x30, 0x06]);
+
+        let mut q_set = None;
+        let mut q_reset = None;
+        while vip.frame_cycle + 2 < Vip::CYCLES_PER_FRAME && q_reset.is_none() {
+            let was = vip.cpu.q;
+            vip.run_slot();
+            let line = vip.frame_cycle / Vip::CYCLES_PER_LINE;
+            match (was, vip.cpu.q) {
+                (false, true) => q_set = Some(line),
+                (true, false) => q_reset = Some(line),
+                _ => {}
+            }
+        }
+        assert_eq!(q_set, Some(Vip::DISPLAY_START - 4));
+        assert_eq!(q_reset, Some(Vip::DISPLAY_START));
+
+        // 表示 OFF の間は EF1 は出ない
+        let mut vip = vip_with_program(&[0x30, 0x00]);
+        vip.bus.display_on = false;
+        vip.run_cycles(Vip::CYCLES_PER_FRAME);
+        assert!(!vip.bus.ef1);
+    }
+}
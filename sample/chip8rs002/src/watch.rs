@@ -0,0 +1,70 @@
+//
+// ROM イメージファイルの変更の監視
+//
+//   更新時刻を一定時間毎に調べ、変わっていれば読み込み直しを知らせる。
+//   書き込み途中のファイルを読まないように、更新時刻が2回続けて同じになるまで待つ。
+//
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// 更新時刻を調べる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct RomWatcher {
+    path: PathBuf,                // 監視するファイル
+    modified: Option<SystemTime>, // 読み込んだときの更新時刻
+    pending: Option<SystemTime>,  // 変更を見つけたときの更新時刻(書き込みの終わり待ち)
+    last_poll: Instant,           // 前回調べた時刻
+}
+
+impl RomWatcher {
+    /// 監視を始める
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> RomWatcher {
+        let path = path.as_ref().to_path_buf();
+        RomWatcher {
+            modified: modified(&path),
+            path,
+            pending: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// ファイルが変更され、読み込み直せる状態になっていれば true
+    ///
+    ///   実行ループから毎回呼んでよい(一定時間毎にしか調べない)
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let now = modified(&self.path);
+        if now.is_none() || now == self.modified {
+            // 消された(置き換え中)か、変わっていない
+            self.pending = None;
+            return false;
+        }
+        if self.pending != now {
+            // 書き込みが終わるまで待つ
+            self.pending = now;
+            return false;
+        }
+
+        // 空のファイルは書き込み途中とみなす
+        let readable = fs::metadata(&self.path).is_ok_and(|m| m.len() > 0);
+        if readable {
+            self.modified = now;
+            self.pending = None;
+        }
+        readable
+    }
+}
+
+/// ファイルの更新時刻
+///
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}